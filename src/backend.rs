@@ -0,0 +1,238 @@
+use crate::{
+    Button, IrRemote, IrSeek, LcdColor, LcdFont, LedColor, MotorPort, MotorType, RgbRaw,
+    SensorColorCode, SensorPort, SensorType, ER,
+};
+
+// The sensor part of Backend, on its own so that SensorDriver can use it
+// (as &dyn SensorBackend) whatever the backend is.
+pub trait SensorBackend {
+    fn sensor_config(&self, port: SensorPort, st: SensorType) -> ER;
+    fn sensor_get_type(&self, port: SensorPort) -> SensorType;
+    fn color_sensor_get_color(&self, port: SensorPort) -> SensorColorCode;
+    fn color_sensor_get_reflect(&self, port: SensorPort) -> u8;
+    fn color_sensor_get_ambient(&self, port: SensorPort) -> u8;
+    fn color_sensor_get_rgb(&self, port: SensorPort) -> RgbRaw;
+    fn gyro_sensor_get_angle(&self, port: SensorPort) -> i16;
+    fn gyro_sensor_get_rate(&self, port: SensorPort) -> i16;
+    fn gyro_sensor_reset(&self, port: SensorPort) -> ER;
+    fn ultrasonic_sensor_get_distance(&self, port: SensorPort) -> i16;
+    fn ultrasonic_sensor_get_distance_nxt(&self, port: SensorPort) -> Option<i16>;
+    fn ultrasonic_sensor_listen(&self, port: SensorPort) -> bool;
+    fn infrared_sensor_get_distance(&self, port: SensorPort) -> u8;
+    fn infrared_sensor_seek(&self, port: SensorPort) -> IrSeek;
+    fn infrared_sensor_get_remote(&self, port: SensorPort) -> IrRemote;
+    fn touch_sensor_is_pressed(&self, port: SensorPort) -> bool;
+    fn ht_nxt_accel_sensor_measure(&self, port: SensorPort) -> Option<[i16; 3]>;
+    fn ht_nxt_color_sensor_measure_color(&self, port: SensorPort) -> Option<u8>;
+    fn ht_nxt_color_sensor_measure_rgb(&self, port: SensorPort) -> Option<RgbRaw>;
+    fn nxt_temp_sensor_measure(&self, port: SensorPort) -> Option<f32>;
+}
+
+// Everything the high level API needs from the brick.
+// Implementations are cheap handles: every component keeps its own copy.
+pub trait Backend: SensorBackend + Copy {
+    fn motor_config(&self, port: MotorPort, mt: MotorType) -> ER;
+    fn motor_get_counts(&self, port: MotorPort) -> i32;
+    fn motor_reset_counts(&self, port: MotorPort) -> ER;
+    fn motor_set_power(&self, port: MotorPort, power: i32) -> ER;
+    fn motor_stop(&self, port: MotorPort, brake: bool) -> ER;
+
+    fn lcd_set_font(&self, font: LcdFont) -> ER;
+    fn lcd_draw_string(&self, s: &str, x: i32, y: i32) -> ER;
+    fn lcd_draw_line(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> ER;
+    fn lcd_fill_rect(&self, x: i32, y: i32, w: i32, h: i32, color: LcdColor) -> ER;
+
+    fn led_set_color(&self, color: LedColor) -> ER;
+    fn button_is_pressed(&self, button: Button) -> bool;
+
+    fn get_utime(&self) -> u64;
+    fn msleep(&self, ms: i32) -> ER;
+    fn abort(&self) -> !;
+}
+
+// The real brick, through the ev3rt bindings.
+#[derive(Clone, Copy, Default)]
+pub struct Ev3rt;
+
+// EV3RT functions not (yet) wrapped by the ev3rt crate.
+#[cfg(target_arch = "arm")]
+extern "C" {
+    fn ev3_ultrasonic_sensor_listen(port: SensorPort) -> ev3rt::BoolT;
+    fn nxt_ultrasonic_sensor_get_distance(port: SensorPort, distance: *mut i16) -> ev3rt::BoolT;
+    fn ev3_infrared_sensor_seek(port: SensorPort) -> IrSeek;
+    fn ev3_infrared_sensor_get_remote(port: SensorPort) -> IrRemote;
+    fn ev3_touch_sensor_is_pressed(port: SensorPort) -> ev3rt::BoolT;
+    fn ht_nxt_accel_sensor_measure(port: SensorPort, axes: *mut i16) -> ev3rt::BoolT;
+    fn ht_nxt_color_sensor_measure_color(port: SensorPort, color: *mut u8) -> ev3rt::BoolT;
+    fn ht_nxt_color_sensor_measure_rgb(port: SensorPort, val: *mut RgbRaw) -> ev3rt::BoolT;
+    fn nxt_temp_sensor_measure(port: SensorPort, temp: *mut f32) -> ev3rt::BoolT;
+}
+
+#[cfg(target_arch = "arm")]
+impl SensorBackend for Ev3rt {
+    fn sensor_config(&self, port: SensorPort, st: SensorType) -> ER {
+        ev3rt::sensor_config(port, st)
+    }
+
+    fn sensor_get_type(&self, port: SensorPort) -> SensorType {
+        ev3rt::sensor_get_type(port)
+    }
+
+    fn color_sensor_get_color(&self, port: SensorPort) -> SensorColorCode {
+        ev3rt::color_sensor_get_color(port)
+    }
+
+    fn color_sensor_get_reflect(&self, port: SensorPort) -> u8 {
+        ev3rt::color_sensor_get_reflect(port)
+    }
+
+    fn color_sensor_get_ambient(&self, port: SensorPort) -> u8 {
+        ev3rt::color_sensor_get_ambient(port)
+    }
+
+    fn color_sensor_get_rgb(&self, port: SensorPort) -> RgbRaw {
+        ev3rt::color_sensor_get_rgb(port)
+    }
+
+    fn gyro_sensor_get_angle(&self, port: SensorPort) -> i16 {
+        ev3rt::gyro_sensor_get_angle(port)
+    }
+
+    fn gyro_sensor_get_rate(&self, port: SensorPort) -> i16 {
+        ev3rt::gyro_sensor_get_rate(port)
+    }
+
+    fn gyro_sensor_reset(&self, port: SensorPort) -> ER {
+        ev3rt::gyro_sensor_reset(port)
+    }
+
+    fn ultrasonic_sensor_get_distance(&self, port: SensorPort) -> i16 {
+        ev3rt::ultrasonic_sensor_get_distance(port)
+    }
+
+    // not through ev3rt::ultrasonic_sensor_get_distance_nxt, that drops the
+    // I2C result and reads 0 with nothing plugged
+    fn ultrasonic_sensor_get_distance_nxt(&self, port: SensorPort) -> Option<i16> {
+        let mut distance = 0i16;
+        if unsafe { nxt_ultrasonic_sensor_get_distance(port, &mut distance) } != 0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn ultrasonic_sensor_listen(&self, port: SensorPort) -> bool {
+        unsafe { ev3_ultrasonic_sensor_listen(port) != 0 }
+    }
+
+    fn infrared_sensor_get_distance(&self, port: SensorPort) -> u8 {
+        ev3rt::infrared_sensor_get_distance(port)
+    }
+
+    fn infrared_sensor_seek(&self, port: SensorPort) -> IrSeek {
+        unsafe { ev3_infrared_sensor_seek(port) }
+    }
+
+    fn infrared_sensor_get_remote(&self, port: SensorPort) -> IrRemote {
+        unsafe { ev3_infrared_sensor_get_remote(port) }
+    }
+
+    fn touch_sensor_is_pressed(&self, port: SensorPort) -> bool {
+        unsafe { ev3_touch_sensor_is_pressed(port) != 0 }
+    }
+
+    fn ht_nxt_accel_sensor_measure(&self, port: SensorPort) -> Option<[i16; 3]> {
+        let mut axes = [0i16; 3];
+        if unsafe { ht_nxt_accel_sensor_measure(port, axes.as_mut_ptr()) } != 0 {
+            Some(axes)
+        } else {
+            None
+        }
+    }
+
+    fn ht_nxt_color_sensor_measure_color(&self, port: SensorPort) -> Option<u8> {
+        let mut color = 0u8;
+        if unsafe { ht_nxt_color_sensor_measure_color(port, &mut color) } != 0 {
+            Some(color)
+        } else {
+            None
+        }
+    }
+
+    fn ht_nxt_color_sensor_measure_rgb(&self, port: SensorPort) -> Option<RgbRaw> {
+        let mut rgb = RgbRaw { r: 0, g: 0, b: 0 };
+        if unsafe { ht_nxt_color_sensor_measure_rgb(port, &mut rgb) } != 0 {
+            Some(rgb)
+        } else {
+            None
+        }
+    }
+
+    fn nxt_temp_sensor_measure(&self, port: SensorPort) -> Option<f32> {
+        let mut temp = 0f32;
+        if unsafe { nxt_temp_sensor_measure(port, &mut temp) } != 0 {
+            Some(temp)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(target_arch = "arm")]
+impl Backend for Ev3rt {
+    fn motor_config(&self, port: MotorPort, mt: MotorType) -> ER {
+        ev3rt::motor_config(port, mt)
+    }
+
+    fn motor_get_counts(&self, port: MotorPort) -> i32 {
+        ev3rt::motor_get_counts(port)
+    }
+
+    fn motor_reset_counts(&self, port: MotorPort) -> ER {
+        ev3rt::motor_reset_counts(port)
+    }
+
+    fn motor_set_power(&self, port: MotorPort, power: i32) -> ER {
+        ev3rt::motor_set_power(port, power)
+    }
+
+    fn motor_stop(&self, port: MotorPort, brake: bool) -> ER {
+        ev3rt::motor_stop(port, brake)
+    }
+
+    fn lcd_set_font(&self, font: LcdFont) -> ER {
+        ev3rt::lcd_set_font(font)
+    }
+
+    fn lcd_draw_string(&self, s: &str, x: i32, y: i32) -> ER {
+        ev3rt::lcd_draw_string(s, x, y)
+    }
+
+    fn lcd_draw_line(&self, x0: i32, y0: i32, x1: i32, y1: i32) -> ER {
+        ev3rt::lcd_draw_line(x0, y0, x1, y1)
+    }
+
+    fn lcd_fill_rect(&self, x: i32, y: i32, w: i32, h: i32, color: LcdColor) -> ER {
+        ev3rt::lcd_fill_rect(x, y, w, h, color)
+    }
+
+    fn led_set_color(&self, color: LedColor) -> ER {
+        ev3rt::led_set_color(color)
+    }
+
+    fn button_is_pressed(&self, button: Button) -> bool {
+        ev3rt::button_is_pressed(button)
+    }
+
+    fn get_utime(&self) -> u64 {
+        ev3rt::get_utime()
+    }
+
+    fn msleep(&self, ms: i32) -> ER {
+        ev3rt::msleep(ms)
+    }
+
+    fn abort(&self) -> ! {
+        ev3rt::abort()
+    }
+}
@@ -0,0 +1,485 @@
+use crate::isqrt;
+
+pub const COLOR_CALIBRATION_SAMPLES: i32 = 16;
+pub const COLOR_PROFILE_BYTES: usize = 20;
+
+// Calibration of a color sensor: black and white references for REFLECT,
+// white reference for RGB.
+// The defaults leave reflect values scaled to 0-1000 and RGB values as they
+// are, until real references are recorded.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ColorProfile {
+    pub black: i32,
+    pub white: i32,
+    pub white_rgb: [i32; 3],
+}
+
+impl ColorProfile {
+    pub fn new() -> Self {
+        Self {
+            black: 0,
+            white: 100,
+            white_rgb: [1000, 1000, 1000],
+        }
+    }
+
+    // 0 (black) - 1000 (white)
+    pub fn normalize_reflect(&self, reflect: i32) -> i32 {
+        let range = self.white - self.black;
+        if range <= 0 {
+            return 0;
+        }
+        ((reflect - self.black) * 1000 / range).clamp(0, 1000)
+    }
+
+    // Every channel 0-1000, with the white reference at 1000
+    pub fn balance_rgb(&self, r: i32, g: i32, b: i32) -> (i32, i32, i32) {
+        let scale = |v: i32, w: i32| {
+            if w <= 0 {
+                0
+            } else {
+                (v * 1000 / w).clamp(0, 1000)
+            }
+        };
+        (
+            scale(r, self.white_rgb[0]),
+            scale(g, self.white_rgb[1]),
+            scale(b, self.white_rgb[2]),
+        )
+    }
+
+    pub fn to_bytes(&self) -> [u8; COLOR_PROFILE_BYTES] {
+        let mut bytes = [0; COLOR_PROFILE_BYTES];
+        let values = [
+            self.black,
+            self.white,
+            self.white_rgb[0],
+            self.white_rgb[1],
+            self.white_rgb[2],
+        ];
+        for (i, v) in values.iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    // None if the bytes do not describe a usable profile
+    pub fn from_bytes(bytes: &[u8; COLOR_PROFILE_BYTES]) -> Option<Self> {
+        let mut values = [0; 5];
+        for (i, v) in values.iter_mut().enumerate() {
+            let mut b = [0; 4];
+            b.copy_from_slice(&bytes[i * 4..i * 4 + 4]);
+            *v = i32::from_le_bytes(b);
+        }
+        let profile = Self {
+            black: values[0],
+            white: values[1],
+            white_rgb: [values[2], values[3], values[4]],
+        };
+        if profile.white > profile.black && profile.white_rgb.iter().all(|w| *w > 0) {
+            Some(profile)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ColorProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const MAX_COLOR_CLASSES: usize = 8;
+const COLOR_CLASS_BYTES: usize = 17;
+pub const COLOR_CLASSIFIER_BYTES: usize = 5 + MAX_COLOR_CLASSES * COLOR_CLASS_BYTES;
+
+#[derive(Clone, Copy)]
+struct ColorClass {
+    label: u8,
+    samples: i32,
+    sum: [i32; 3],
+}
+
+impl ColorClass {
+    fn centroid(&self) -> [i32; 3] {
+        [
+            self.sum[0] / self.samples,
+            self.sum[1] / self.samples,
+            self.sum[2] / self.samples,
+        ]
+    }
+}
+
+// Nearest centroid classifier on RGB values (balanced ones, see
+// SensorData::train_color), for colors the sensor itself does not know.
+// Labels are up to the program; a value farther than max_distance from
+// every centroid is not classified.
+#[derive(Clone, Copy)]
+pub struct ColorClassifier {
+    max_distance: i32,
+    count: usize,
+    classes: [ColorClass; MAX_COLOR_CLASSES],
+}
+
+impl ColorClassifier {
+    pub fn new(max_distance: i32) -> Self {
+        Self {
+            max_distance,
+            count: 0,
+            classes: [ColorClass {
+                label: 0,
+                samples: 0,
+                sum: [0; 3],
+            }; MAX_COLOR_CLASSES],
+        }
+    }
+
+    pub fn max_distance(&self) -> i32 {
+        self.max_distance
+    }
+
+    pub fn set_max_distance(&mut self, max_distance: i32) {
+        self.max_distance = max_distance;
+    }
+
+    // False if the label is new and there is no room for another class
+    pub fn add_sample(&mut self, label: u8, rgb: (i32, i32, i32)) -> bool {
+        let idx = match self.classes[..self.count]
+            .iter()
+            .position(|c| c.label == label)
+        {
+            Some(idx) => idx,
+            None if self.count < MAX_COLOR_CLASSES => {
+                self.classes[self.count] = ColorClass {
+                    label,
+                    samples: 0,
+                    sum: [0; 3],
+                };
+                self.count += 1;
+                self.count - 1
+            }
+            None => return false,
+        };
+        let class = &mut self.classes[idx];
+        class.samples += 1;
+        class.sum[0] += rgb.0;
+        class.sum[1] += rgb.1;
+        class.sum[2] += rgb.2;
+        true
+    }
+
+    pub fn forget(&mut self, label: u8) {
+        if let Some(idx) = self.classes[..self.count]
+            .iter()
+            .position(|c| c.label == label)
+        {
+            self.classes.copy_within(idx + 1..self.count, idx);
+            self.count -= 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = u8> + '_ {
+        self.classes[..self.count].iter().map(|c| c.label)
+    }
+
+    pub fn centroid(&self, label: u8) -> Option<(i32, i32, i32)> {
+        let c = self.classes[..self.count]
+            .iter()
+            .find(|c| c.label == label)?
+            .centroid();
+        Some((c[0], c[1], c[2]))
+    }
+
+    // Nearest label and its distance, even if beyond max_distance
+    pub fn nearest(&self, rgb: (i32, i32, i32)) -> Option<(u8, i32)> {
+        let mut best: Option<(u8, i64)> = None;
+        for class in self.classes[..self.count].iter() {
+            let c = class.centroid();
+            let d = [rgb.0 - c[0], rgb.1 - c[1], rgb.2 - c[2]];
+            let d2 = d.iter().map(|d| *d as i64 * *d as i64).sum::<i64>();
+            match best {
+                Some((_, best_d2)) if best_d2 <= d2 => {}
+                _ => best = Some((class.label, d2)),
+            }
+        }
+        best.map(|(label, d2)| (label, isqrt(d2) as i32))
+    }
+
+    pub fn classify(&self, rgb: (i32, i32, i32)) -> Option<u8> {
+        match self.nearest(rgb) {
+            Some((label, d)) if d <= self.max_distance => Some(label),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; COLOR_CLASSIFIER_BYTES] {
+        let mut bytes = [0; COLOR_CLASSIFIER_BYTES];
+        bytes[0..4].copy_from_slice(&self.max_distance.to_le_bytes());
+        bytes[4] = self.count as u8;
+        for (i, class) in self.classes[..self.count].iter().enumerate() {
+            let b = &mut bytes[5 + i * COLOR_CLASS_BYTES..5 + (i + 1) * COLOR_CLASS_BYTES];
+            b[0] = class.label;
+            b[1..5].copy_from_slice(&class.samples.to_le_bytes());
+            for ch in 0..3 {
+                b[5 + ch * 4..9 + ch * 4].copy_from_slice(&class.sum[ch].to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    // None if the bytes do not describe a classifier
+    pub fn from_bytes(bytes: &[u8; COLOR_CLASSIFIER_BYTES]) -> Option<Self> {
+        let read = |b: &[u8]| {
+            let mut v = [0; 4];
+            v.copy_from_slice(&b[..4]);
+            i32::from_le_bytes(v)
+        };
+        let mut classifier = Self::new(read(&bytes[0..4]));
+        let count = bytes[4] as usize;
+        if count > MAX_COLOR_CLASSES {
+            return None;
+        }
+        for i in 0..count {
+            let b = &bytes[5 + i * COLOR_CLASS_BYTES..5 + (i + 1) * COLOR_CLASS_BYTES];
+            let class = ColorClass {
+                label: b[0],
+                samples: read(&b[1..5]),
+                sum: [read(&b[5..9]), read(&b[9..13]), read(&b[13..17])],
+            };
+            if class.samples <= 0 || classifier.labels().any(|l| l == class.label) {
+                return None;
+            }
+            classifier.classes[i] = class;
+            classifier.count += 1;
+        }
+        Some(classifier)
+    }
+}
+
+// Hue in deg (0-359), the rest 0-1000, from RGB values 0-1000
+// (see ColorProfile::balance_rgb)
+#[derive(Clone, Copy, PartialEq)]
+pub struct Hsv {
+    pub h: i32,
+    pub s: i32,
+    pub v: i32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Hsl {
+    pub h: i32,
+    pub s: i32,
+    pub l: i32,
+}
+
+fn hue(r: i32, g: i32, b: i32, max: i32, delta: i32) -> i32 {
+    if delta == 0 {
+        return 0;
+    }
+    let h = if max == r {
+        60 * (g - b) / delta
+    } else if max == g {
+        120 + 60 * (b - r) / delta
+    } else {
+        240 + 60 * (r - g) / delta
+    };
+    (h + 360) % 360
+}
+
+impl Hsv {
+    pub fn from_rgb(rgb: (i32, i32, i32)) -> Self {
+        let (r, g, b) = rgb;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        Self {
+            h: hue(r, g, b, max, delta),
+            s: if max > 0 { delta * 1000 / max } else { 0 },
+            v: max.clamp(0, 1000),
+        }
+    }
+}
+
+impl Hsl {
+    pub fn from_rgb(rgb: (i32, i32, i32)) -> Self {
+        let (r, g, b) = rgb;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let den = 1000 - (max + min - 1000).abs();
+        Self {
+            h: hue(r, g, b, max, delta),
+            s: if den > 0 {
+                (delta * 1000 / den).clamp(0, 1000)
+            } else {
+                0
+            },
+            l: ((max + min) / 2).clamp(0, 1000),
+        }
+    }
+}
+
+pub const MAX_HUE_BANDS: usize = 8;
+
+// Hues from min_hue to max_hue (wrapping around 0 when min_hue > max_hue,
+// as for red), ignored below the minimum saturation and value: there the
+// hue means little.
+#[derive(Clone, Copy, PartialEq)]
+pub struct HueBand {
+    pub label: u8,
+    pub min_hue: i32,
+    pub max_hue: i32,
+    pub min_saturation: i32,
+    pub min_value: i32,
+}
+
+impl HueBand {
+    pub fn contains(&self, hsv: Hsv) -> bool {
+        let in_range = if self.min_hue <= self.max_hue {
+            hsv.h >= self.min_hue && hsv.h <= self.max_hue
+        } else {
+            hsv.h >= self.min_hue || hsv.h <= self.max_hue
+        };
+        in_range && hsv.s >= self.min_saturation && hsv.v >= self.min_value
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct HueMatch {
+    pub label: u8,
+    pub hsv: Hsv,
+    // saturation * value, 0-1000: pale or dark readings are less certain
+    pub confidence: i32,
+}
+
+#[derive(Clone, Copy)]
+pub struct HueMatcher {
+    bands: [HueBand; MAX_HUE_BANDS],
+    count: usize,
+}
+
+impl HueMatcher {
+    pub fn new() -> Self {
+        Self {
+            bands: [HueBand {
+                label: 0,
+                min_hue: 0,
+                max_hue: 0,
+                min_saturation: 0,
+                min_value: 0,
+            }; MAX_HUE_BANDS],
+            count: 0,
+        }
+    }
+
+    // False when there is no room for another band
+    pub fn add(&mut self, band: HueBand) -> bool {
+        if self.count == MAX_HUE_BANDS {
+            return false;
+        }
+        self.bands[self.count] = band;
+        self.count += 1;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+    }
+
+    pub fn bands(&self) -> &[HueBand] {
+        &self.bands[..self.count]
+    }
+
+    // The first band that contains the color
+    pub fn find(&self, hsv: Hsv) -> Option<HueMatch> {
+        let band = self.bands().iter().find(|b| b.contains(hsv))?;
+        Some(HueMatch {
+            label: band.label,
+            hsv,
+            confidence: hsv.s * hsv.v / 1000,
+        })
+    }
+}
+
+impl Default for HueMatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile() {
+        let profile = ColorProfile {
+            black: 5,
+            white: 85,
+            white_rgb: [200, 250, 100],
+        };
+        assert_eq!(profile.normalize_reflect(45), 500);
+        assert_eq!(profile.normalize_reflect(0), 0);
+        assert_eq!(profile.normalize_reflect(99), 1000);
+        assert!(profile.balance_rgb(100, 250, 300) == (500, 1000, 1000));
+        assert!(ColorProfile::from_bytes(&profile.to_bytes()) == Some(profile));
+        assert!(ColorProfile::from_bytes(&[0; COLOR_PROFILE_BYTES]).is_none());
+    }
+
+    #[test]
+    fn classifier() {
+        let mut classifier = ColorClassifier::new(100);
+        assert!(classifier.add_sample(1, (900, 100, 100)));
+        assert!(classifier.add_sample(1, (700, 100, 100)));
+        assert!(classifier.add_sample(2, (100, 100, 800)));
+        assert!(classifier.centroid(1) == Some((800, 100, 100)));
+        assert_eq!(classifier.classify((780, 120, 90)), Some(1));
+        assert_eq!(classifier.classify((500, 500, 500)), None);
+        assert!(classifier.nearest((100, 100, 700)) == Some((2, 100)));
+
+        let copy = ColorClassifier::from_bytes(&classifier.to_bytes()).unwrap();
+        assert_eq!(copy.max_distance(), 100);
+        assert!(copy.labels().eq(classifier.labels()));
+        assert!(copy.centroid(2) == classifier.centroid(2));
+
+        classifier.forget(1);
+        assert_eq!(classifier.classify((800, 100, 100)), None);
+        for label in 2..=MAX_COLOR_CLASSES as u8 + 1 {
+            classifier.add_sample(label, (0, 0, 0));
+        }
+        assert!(!classifier.add_sample(100, (0, 0, 0)));
+    }
+
+    #[test]
+    fn hue() {
+        let red = Hsv::from_rgb((1000, 0, 0));
+        assert!(
+            red == Hsv {
+                h: 0,
+                s: 1000,
+                v: 1000
+            }
+        );
+        assert_eq!(Hsv::from_rgb((0, 0, 1000)).h, 240);
+        assert_eq!(Hsv::from_rgb((1000, 0, 500)).h, 330);
+        assert!(Hsl::from_rgb((500, 500, 500)) == Hsl { h: 0, s: 0, l: 500 });
+
+        let mut matcher = HueMatcher::new();
+        matcher.add(HueBand {
+            label: 1,
+            min_hue: 330,
+            max_hue: 20,
+            min_saturation: 300,
+            min_value: 200,
+        });
+        assert_eq!(matcher.find(red).map(|m| m.label), Some(1));
+        assert_eq!(matcher.find(red).map(|m| m.confidence), Some(1000));
+        assert!(matcher.find(Hsv::from_rgb((0, 1000, 0))).is_none());
+        assert!(matcher.find(Hsv::from_rgb((100, 80, 80))).is_none());
+    }
+}
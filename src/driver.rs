@@ -0,0 +1,55 @@
+use core::any::Any;
+
+use crate::{Duration, SensorBackend, SensorPort, SensorType};
+
+// A sensor that is not known to this crate, or a new way to read a known
+// one. The port is configured with sensor_type() like any other sensor,
+// then the driver reads it. The backend it is given only has the ev3rt
+// sensor calls of SensorBackend: ev3rt exposes no raw I2C or UART access,
+// so a driver for a device those calls do not cover (a compass, an IR
+// seeker v2) does its own FFI to the EV3RT C API, and only runs on the
+// brick. Drivers built on the SensorBackend calls run against the mock too.
+//
+//     struct Compass;
+//     impl SensorDriver for Compass { ... }
+//     static COMPASS: Compass = Compass;
+//
+//     ev3.s1().configure(SensorConfiguration::Custom(DriverRef(&COMPASS)));
+pub trait SensorDriver: Any {
+    fn sensor_type(&self) -> SensorType;
+
+    // Called once the port is configured: false if the device is not there
+    // or does not answer as expected
+    fn init(&self, _hw: &dyn SensorBackend, _port: SensorPort) -> bool {
+        true
+    }
+
+    // Up to four values, reported as SensorReading::Custom; None if the
+    // device did not answer
+    fn read(&self, hw: &dyn SensorBackend, port: SensorPort) -> Option<[i16; 4]>;
+
+    fn read_period(&self) -> Duration {
+        Duration::zero()
+    }
+}
+
+// Drivers are compared by type and address, so that configurations stay
+// comparable (statics of different zero sized types may share an address)
+#[derive(Clone, Copy)]
+pub struct DriverRef(pub &'static dyn SensorDriver);
+
+impl DriverRef {
+    pub fn driver(&self) -> &'static dyn SensorDriver {
+        self.0
+    }
+}
+
+impl PartialEq for DriverRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.type_id() == other.0.type_id()
+            && core::ptr::eq(
+                self.0 as *const dyn SensorDriver as *const u8,
+                other.0 as *const dyn SensorDriver as *const u8,
+            )
+    }
+}
@@ -0,0 +1,74 @@
+use crate::{MotorPort, MotorType, SensorConfiguration, SensorPort, SensorType, ER};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConfigErrorReason {
+    // ev3rt refused the configuration
+    Driver(ER),
+    // detection found another device (or none at all) in the port
+    WrongDevice(SensorType),
+    UnsupportedMode,
+    // the first reading made no sense, or the device did not answer
+    InvalidReading,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConfigError {
+    Sensor {
+        port: SensorPort,
+        cfg: SensorConfiguration,
+        reason: ConfigErrorReason,
+    },
+    Motor {
+        port: MotorPort,
+        cfg: MotorType,
+        reason: ConfigErrorReason,
+    },
+}
+
+impl ConfigError {
+    pub fn reason(&self) -> ConfigErrorReason {
+        match self {
+            ConfigError::Sensor { reason, .. } | ConfigError::Motor { reason, .. } => *reason,
+        }
+    }
+}
+
+// Outcome of Ev3::apply_configuration.
+// Motors are configured once; sensors are retried until they succeed or
+// SENSOR_CONFIG_TIMEOUT passes. Either way an entry is the error of the last
+// attempt, None if it worked.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ConfigReport {
+    pub sensors: [Option<ConfigError>; 4],
+    pub motors: [Option<ConfigError>; 4],
+}
+
+impl ConfigReport {
+    pub fn new() -> Self {
+        Self {
+            sensors: [None; 4],
+            motors: [None; 4],
+        }
+    }
+
+    pub fn sensor(&self, port: SensorPort) -> Option<ConfigError> {
+        self.sensors[port as usize]
+    }
+
+    pub fn motor(&self, port: MotorPort) -> Option<ConfigError> {
+        self.motors[port as usize]
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.sensors
+            .iter()
+            .chain(self.motors.iter())
+            .all(|e| e.is_none())
+    }
+}
+
+impl Default for ConfigReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,265 @@
+pub const FILTER_WINDOW: usize = 8;
+pub const MAX_FILTERS: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorFilter {
+    None,
+    // average of the last n values (n up to FILTER_WINDOW)
+    MovingAverage(usize),
+    // median of the last n values (n up to FILTER_WINDOW)
+    Median(usize),
+    // alpha in 1/1000: out += (in - out) * alpha / 1000
+    Exponential(i32),
+    // Invalid readings and jumps bigger than max_jump are replaced by the
+    // last good value, but at most max_held times in a row (after that the
+    // new value is taken as real).
+    Glitch { max_jump: i32, max_held: u8 },
+}
+
+#[derive(Clone, Copy)]
+struct FilterState {
+    window: [i32; FILTER_WINDOW],
+    count: usize,
+    next: usize,
+    last: Option<i32>,
+    held: u8,
+}
+
+impl FilterState {
+    fn new() -> Self {
+        Self {
+            window: [0; FILTER_WINDOW],
+            count: 0,
+            next: 0,
+            last: None,
+            held: 0,
+        }
+    }
+
+    fn push(&mut self, v: i32, n: usize) -> usize {
+        let n = n.clamp(1, FILTER_WINDOW);
+        self.window[self.next] = v;
+        self.next = (self.next + 1) % FILTER_WINDOW;
+        if self.count < n {
+            self.count += 1;
+        }
+        self.count.min(n)
+    }
+
+    fn latest(&self, n: usize) -> impl Iterator<Item = i32> + '_ {
+        (0..n).map(move |i| self.window[(self.next + FILTER_WINDOW - 1 - i) % FILTER_WINDOW])
+    }
+
+    fn apply(&mut self, f: SensorFilter, v: Option<i32>) -> Option<i32> {
+        match f {
+            SensorFilter::None => v,
+            SensorFilter::MovingAverage(n) => {
+                let v = v?;
+                let n = self.push(v, n);
+                Some(self.latest(n).sum::<i32>() / n as i32)
+            }
+            SensorFilter::Median(n) => {
+                let v = v?;
+                let n = self.push(v, n);
+                let mut sorted = [0; FILTER_WINDOW];
+                for (i, x) in self.latest(n).enumerate() {
+                    sorted[i] = x;
+                }
+                let sorted = &mut sorted[..n];
+                sorted.sort_unstable();
+                Some(sorted[n / 2])
+            }
+            SensorFilter::Exponential(alpha) => {
+                let v = v?;
+                let out = match self.last {
+                    Some(last) => last + (v - last) * alpha / 1000,
+                    None => v,
+                };
+                self.last = Some(out);
+                Some(out)
+            }
+            SensorFilter::Glitch { max_jump, max_held } => {
+                let glitch = match (v, self.last) {
+                    (None, _) => true,
+                    (Some(v), Some(last)) => (v - last).abs() > max_jump,
+                    (Some(_), None) => false,
+                };
+                if glitch && self.last.is_some() && self.held < max_held {
+                    self.held += 1;
+                    self.last
+                } else {
+                    self.held = 0;
+                    if v.is_some() {
+                        self.last = v;
+                    }
+                    v
+                }
+            }
+        }
+    }
+}
+
+// A chain of up to MAX_FILTERS filters, applied to the data channels of a
+// sensor that hold plain numbers. The others (codes, flags, packed values)
+// are passed through as they are.
+#[derive(Clone, Copy)]
+pub struct FilterChain {
+    filters: [SensorFilter; MAX_FILTERS],
+    len: usize,
+    state: [[FilterState; 4]; MAX_FILTERS],
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self {
+            filters: [SensorFilter::None; MAX_FILTERS],
+            len: 0,
+            state: [[FilterState::new(); 4]; MAX_FILTERS],
+        }
+    }
+
+    pub fn set(&mut self, filters: &[SensorFilter]) {
+        *self = Self::new();
+        for (i, f) in filters.iter().take(MAX_FILTERS).enumerate() {
+            self.filters[i] = *f;
+            self.len = i + 1;
+        }
+    }
+
+    pub fn filters(&self) -> &[SensorFilter] {
+        &self.filters[..self.len]
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [[FilterState::new(); 4]; MAX_FILTERS];
+    }
+
+    // None when a scalar channel has no value (or with no values and no
+    // scalar channel). Non-scalar channels have nothing to hold, without
+    // values they get the invalid sentinel of SensorData::data.
+    pub fn process(&mut self, values: Option<[i32; 4]>, scalar: [bool; 4]) -> Option<[i32; 4]> {
+        let mut out = [None; 4];
+        for ch in 0..4 {
+            let mut v = values.map(|values| values[ch]);
+            if !scalar[ch] {
+                out[ch] = v.or(Some(-1));
+                continue;
+            }
+            for (f, state) in self.filters[..self.len].iter().zip(self.state.iter_mut()) {
+                v = state[ch].apply(*f, v);
+            }
+            out[ch] = v;
+        }
+        if values.is_none() && !scalar.contains(&true) {
+            return None;
+        }
+        match out {
+            [Some(a), Some(b), Some(c), Some(d)] => Some([a, b, c, d]),
+            _ => None,
+        }
+    }
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCALAR: [bool; 4] = [true, false, false, false];
+
+    fn run(chain: &mut FilterChain, v: i32) -> i32 {
+        chain.process(Some([v, 0, 0, 0]), SCALAR).unwrap()[0]
+    }
+
+    #[test]
+    fn moving_average() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::MovingAverage(4)]);
+        assert_eq!(run(&mut chain, 10), 10);
+        assert_eq!(run(&mut chain, 20), 15);
+        for _ in 0..4 {
+            run(&mut chain, 40);
+        }
+        assert_eq!(run(&mut chain, 40), 40);
+    }
+
+    #[test]
+    fn median_drops_spikes() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::Median(3)]);
+        run(&mut chain, 10);
+        run(&mut chain, 11);
+        assert_eq!(run(&mut chain, 500), 11);
+        assert_eq!(run(&mut chain, 12), 12);
+    }
+
+    #[test]
+    fn exponential() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::Exponential(500)]);
+        assert_eq!(run(&mut chain, 0), 0);
+        assert_eq!(run(&mut chain, 100), 50);
+        assert_eq!(run(&mut chain, 100), 75);
+    }
+
+    #[test]
+    fn glitch_holds_then_gives_in() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::Glitch {
+            max_jump: 10,
+            max_held: 2,
+        }]);
+        assert_eq!(run(&mut chain, 100), 100);
+        assert_eq!(run(&mut chain, 200), 100);
+        assert_eq!(run(&mut chain, 200), 100);
+        assert_eq!(run(&mut chain, 200), 200);
+        assert_eq!(run(&mut chain, 205), 205);
+    }
+
+    #[test]
+    fn glitch_holds_across_invalid_readings() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::Glitch {
+            max_jump: 10,
+            max_held: 1,
+        }]);
+        assert_eq!(run(&mut chain, 50), 50);
+        assert!(chain.process(None, SCALAR) == Some([50, -1, -1, -1]));
+        assert!(chain.process(None, SCALAR).is_none());
+        assert_eq!(run(&mut chain, 52), 52);
+    }
+
+    #[test]
+    fn no_values_without_scalar_channels() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::Glitch {
+            max_jump: 10,
+            max_held: 1,
+        }]);
+        assert!(chain.process(Some([3, 0, 0, 0]), [false; 4]) == Some([3, 0, 0, 0]));
+        assert!(chain.process(None, [false; 4]).is_none());
+    }
+
+    #[test]
+    fn only_scalar_channels() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::MovingAverage(2)]);
+        chain.process(Some([0, 0, 0, 0]), [true, false, true, false]);
+        let out = chain.process(Some([10, 10, 10, 10]), [true, false, true, false]);
+        assert!(out == Some([5, 10, 5, 10]));
+    }
+
+    #[test]
+    fn chain_order_and_limit() {
+        let mut chain = FilterChain::new();
+        chain.set(&[SensorFilter::None; MAX_FILTERS + 2]);
+        assert_eq!(chain.filters().len(), MAX_FILTERS);
+        chain.set(&[]);
+        assert_eq!(run(&mut chain, 7), 7);
+    }
+}
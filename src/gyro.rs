@@ -0,0 +1,150 @@
+use crate::Duration;
+
+pub const GYRO_CALIBRATION_SAMPLES: i32 = 64;
+// |rate - bias| below this (in 1/1000 deg/s) is taken as sensor noise
+const STILL_THRESHOLD: i32 = 1500;
+const STILL_TIME: Duration = Duration::from_msec(500);
+// bias += (rate - bias) / BIAS_TRACKING_FACTOR while standing still
+const BIAS_TRACKING_FACTOR: i64 = 64;
+
+// Bias compensation and heading integration for a gyro in RATE mode.
+// Rates are in 1/1000 deg/s, the heading is kept in 1/1_000_000_000 deg.
+// The bias is only tracked while the robot is known to be stationary (see
+// set_stationary): a slow turn looks just like drift to the gyro itself.
+#[derive(Clone, Copy)]
+pub struct GyroTracker {
+    calibrated: bool,
+    stationary: bool,
+    bias: i32,
+    still_for: Duration,
+    heading: i64,
+}
+
+impl GyroTracker {
+    pub fn new() -> Self {
+        Self {
+            calibrated: false,
+            stationary: false,
+            bias: 0,
+            still_for: Duration::zero(),
+            heading: 0,
+        }
+    }
+
+    pub fn is_calibrated(&self) -> bool {
+        self.calibrated
+    }
+
+    pub fn set_bias(&mut self, bias: i32) {
+        self.bias = bias;
+        self.calibrated = true;
+        self.still_for = Duration::zero();
+    }
+
+    // 1/1000 deg/s
+    pub fn bias(&self) -> i32 {
+        self.bias
+    }
+
+    // Whether nothing moves the robot, from outside the gyro (Ev3::read
+    // sets it when no motor is powered or turning)
+    pub fn set_stationary(&mut self, stationary: bool) {
+        self.stationary = stationary;
+    }
+
+    pub fn is_stationary(&self) -> bool {
+        self.stationary
+    }
+
+    // Stationary and quiet long enough for the bias to be tracked
+    pub fn is_still(&self) -> bool {
+        self.still_for >= STILL_TIME
+    }
+
+    // Takes a raw rate sample (deg/s) and the time since the previous one,
+    // returns the compensated rate in 1/1000 deg/s.
+    pub fn update(&mut self, raw_rate: i16, dt: Duration) -> i32 {
+        let raw = raw_rate as i32 * 1000;
+        let rate = raw - self.bias;
+
+        if self.calibrated && self.stationary && rate.abs() <= STILL_THRESHOLD {
+            self.still_for += dt;
+            if self.is_still() {
+                let bias = self.bias as i64;
+                self.bias = (bias + (raw as i64 - bias) / BIAS_TRACKING_FACTOR) as i32;
+            }
+        } else {
+            self.still_for = Duration::zero();
+        }
+
+        self.heading += rate as i64 * dt.usec() as i64;
+        rate
+    }
+
+    pub fn heading_mdeg(&self) -> i32 {
+        (self.heading / 1_000_000) as i32
+    }
+
+    pub fn heading(&self) -> i32 {
+        (self.heading / 1_000_000_000) as i32
+    }
+
+    pub fn set_heading(&mut self, deg: i32) {
+        self.heading = deg as i64 * 1_000_000_000;
+    }
+}
+
+impl Default for GyroTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_msec(10);
+
+    #[test]
+    fn integrates_compensated_rate() {
+        let mut gyro = GyroTracker::new();
+        gyro.set_bias(2000);
+        for _ in 0..100 {
+            assert_eq!(gyro.update(92, DT), 90_000);
+        }
+        assert_eq!(gyro.heading(), 90);
+        gyro.set_heading(-5);
+        assert_eq!(gyro.heading_mdeg(), -5000);
+    }
+
+    #[test]
+    fn tracks_bias_only_while_stationary() {
+        let mut gyro = GyroTracker::new();
+        gyro.set_bias(0);
+        for _ in 0..200 {
+            gyro.update(1, DT);
+        }
+        assert_eq!(gyro.bias(), 0);
+        assert!(!gyro.is_still());
+
+        gyro.set_stationary(true);
+        for _ in 0..1000 {
+            gyro.update(1, DT);
+        }
+        assert!(gyro.is_still());
+        assert!(gyro.bias() > 900 && gyro.bias() <= 1000);
+    }
+
+    #[test]
+    fn no_tracking_before_calibration() {
+        let mut gyro = GyroTracker::new();
+        gyro.set_stationary(true);
+        for _ in 0..1000 {
+            gyro.update(1, DT);
+        }
+        assert!(!gyro.is_calibrated());
+        assert_eq!(gyro.bias(), 0);
+        assert_eq!(gyro.heading(), 10);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{Backend, Duration, SensorData};
+
+// The last N values of one data channel of a sensor, with the time between
+// them. Queries look at the samples not older than a window (measured from
+// the newest sample).
+//
+//     let mut wall = SensorHistory::<32>::new(0);
+//     loop {
+//         ev3.read();
+//         wall.update(ev3.s1(), ev3.time.from_last_read());
+//         let approach = wall.rate(Duration::from_msec(200));
+//     }
+#[derive(Clone, Copy)]
+pub struct SensorHistory<const N: usize> {
+    channel: usize,
+    values: [i32; N],
+    // time from the previous sample
+    periods: [Duration; N],
+    next: usize,
+    count: usize,
+    since_last: Duration,
+}
+
+impl<const N: usize> SensorHistory<N> {
+    // channel is the index in SensorData::filtered(), 0-3 (0 for single
+    // values); with any other channel nothing is ever recorded
+    pub fn new(channel: usize) -> Self {
+        Self {
+            channel,
+            values: [0; N],
+            periods: [Duration::zero(); N],
+            next: 0,
+            count: 0,
+            since_last: Duration::zero(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.since_last = Duration::zero();
+    }
+
+    // Call after every read: records the (filtered) value whenever the sensor
+    // got a new one from the hardware
+    pub fn update<B: Backend>(&mut self, sensor: &SensorData<B>, from_last_read: Duration) {
+        self.since_last += from_last_read;
+        if sensor.age() != Some(Duration::zero()) {
+            return;
+        }
+        if let Some(value) = sensor.filtered().and_then(|v| v.get(self.channel).copied()) {
+            let period = self.since_last;
+            self.push(value, period);
+        }
+    }
+
+    pub fn push(&mut self, value: i32, from_previous: Duration) {
+        if N == 0 {
+            return;
+        }
+        self.values[self.next] = value;
+        self.periods[self.next] = from_previous;
+        self.next = (self.next + 1) % N;
+        if self.count < N {
+            self.count += 1;
+        }
+        self.since_last = Duration::zero();
+    }
+
+    pub fn latest(&self) -> Option<i32> {
+        self.iter().next().map(|(_, v)| v)
+    }
+
+    // Newest first, with the age of every sample relative to the newest one
+    pub fn iter(&self) -> impl Iterator<Item = (Duration, i32)> + '_ {
+        let mut age = Duration::zero();
+        (0..self.count).map(move |i| {
+            let idx = (self.next + N - 1 - i) % N;
+            let sample = (age, self.values[idx]);
+            age += self.periods[idx];
+            sample
+        })
+    }
+
+    fn window(&self, window: Duration) -> impl Iterator<Item = (Duration, i32)> + '_ {
+        self.iter().take_while(move |(age, _)| *age <= window)
+    }
+
+    pub fn min(&self, window: Duration) -> Option<i32> {
+        self.window(window).map(|(_, v)| v).min()
+    }
+
+    pub fn max(&self, window: Duration) -> Option<i32> {
+        self.window(window).map(|(_, v)| v).max()
+    }
+
+    pub fn mean(&self, window: Duration) -> Option<i32> {
+        let (n, sum) = self
+            .window(window)
+            .fold((0i64, 0i64), |(n, sum), (_, v)| (n + 1, sum + v as i64));
+        if n == 0 {
+            return None;
+        }
+        Some((sum / n) as i32)
+    }
+
+    pub fn variance(&self, window: Duration) -> Option<i32> {
+        let mean = self.mean(window)? as i64;
+        let (n, sum) = self.window(window).fold((0i64, 0i64), |(n, sum), (_, v)| {
+            let d = v as i64 - mean;
+            (n + 1, sum + d * d)
+        });
+        Some((sum / n) as i32)
+    }
+
+    // Change per second, from a least squares line through the window.
+    // None with less than two samples.
+    pub fn rate(&self, window: Duration) -> Option<i32> {
+        let (n, sum_t, sum_v) = self
+            .window(window)
+            .fold((0i128, 0i128, 0i128), |(n, st, sv), (age, v)| {
+                (n + 1, st - age.usec() as i128, sv + v as i128)
+            });
+        if n < 2 {
+            return None;
+        }
+        let (mut stt, mut stv) = (0i128, 0i128);
+        for (age, v) in self.window(window) {
+            let t = -(age.usec() as i128) * n - sum_t;
+            stt += t * t;
+            stv += t * (v as i128 * n - sum_v);
+        }
+        if stt == 0 {
+            return None;
+        }
+        Some((stv * 1_000_000 / stt) as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_msec(10);
+
+    #[test]
+    fn window_queries() {
+        let mut history = SensorHistory::<8>::new(0);
+        assert!(history.is_empty());
+        assert_eq!(history.rate(DT), None);
+        for v in [10, 20, 30, 40, 50] {
+            history.push(v, DT);
+        }
+        assert_eq!(history.len(), 5);
+        assert_eq!(history.latest(), Some(50));
+        assert_eq!(history.min(Duration::from_msec(20)), Some(30));
+        assert_eq!(history.max(Duration::from_msec(100)), Some(50));
+        assert_eq!(history.mean(Duration::from_msec(20)), Some(40));
+        assert_eq!(history.variance(Duration::from_msec(10)), Some(25));
+        assert_eq!(history.rate(Duration::from_msec(100)), Some(1000));
+    }
+
+    #[test]
+    fn keeps_the_last_n() {
+        let mut history = SensorHistory::<4>::new(0);
+        for v in 0..10 {
+            history.push(v, DT);
+        }
+        assert_eq!(history.len(), 4);
+        assert!(history
+            .iter()
+            .map(|(_, v)| v)
+            .eq([9, 8, 7, 6].iter().copied()));
+        history.clear();
+        assert_eq!(history.latest(), None);
+    }
+}
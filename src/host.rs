@@ -0,0 +1,168 @@
+// Mirror of the ev3rt types, used when building for a host where the ev3rt
+// bindings (and their panic handler) cannot be linked.
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum ER {
+    OK = 0,
+    SYS = -5,
+    NOSPT = -9,
+    RSFN = -10,
+    RSATR = -11,
+    PAR = -17,
+    ID = -18,
+    CTX = -25,
+    MACV = -26,
+    OACV = -27,
+    ILUSE = -28,
+    NOMEM = -33,
+    NOID = -34,
+    NORES = -35,
+    OBJ = -41,
+    NOEXS = -42,
+    QOVR = -43,
+    RLWAI = -49,
+    TMOUT = -50,
+    DLT = -51,
+    CLS = -52,
+    WBLK = -57,
+    BOVR = -58,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum LedColor {
+    OFF = 0,
+    RED = 1,
+    GREEN = 2,
+    ORANGE = 3,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Button {
+    LEFT = 0,
+    RIGHT = 1,
+    UP = 2,
+    DOWN = 3,
+    ENTER = 4,
+    BACK = 5,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum LcdFont {
+    SMALL = 0,
+    MEDIUM = 1,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum LcdColor {
+    WHITE = 0,
+    BLACK = 1,
+}
+
+pub const LCD_WIDTH: i32 = 178;
+pub const LCD_HEIGHT: i32 = 128;
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum MotorPort {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum MotorType {
+    NONE = 0,
+    MEDIUM = 1,
+    LARGE = 2,
+    UNDEGULATED = 3,
+}
+impl From<i32> for MotorType {
+    fn from(t: i32) -> MotorType {
+        match t {
+            1 => MotorType::MEDIUM,
+            2 => MotorType::LARGE,
+            3 => MotorType::UNDEGULATED,
+            _ => MotorType::NONE,
+        }
+    }
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorPort {
+    S1 = 0,
+    S2 = 1,
+    S3 = 2,
+    S4 = 3,
+}
+
+#[repr(i32)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorType {
+    NONE = 0,
+    ULTRASONIC = 1,
+    GYRO = 2,
+    TOUCH = 3,
+    COLOR = 4,
+    INFRARED = 5,
+    HtNxtACCEL = 6,
+    HtNxtCOLOR = 7,
+    NxtULTRASONIC = 8,
+    NxtTEMP = 9,
+}
+impl From<i32> for SensorType {
+    fn from(t: i32) -> SensorType {
+        match t {
+            1 => SensorType::ULTRASONIC,
+            2 => SensorType::GYRO,
+            3 => SensorType::TOUCH,
+            4 => SensorType::COLOR,
+            5 => SensorType::INFRARED,
+            6 => SensorType::HtNxtACCEL,
+            7 => SensorType::HtNxtCOLOR,
+            8 => SensorType::NxtULTRASONIC,
+            9 => SensorType::NxtTEMP,
+            _ => SensorType::NONE,
+        }
+    }
+}
+
+#[repr(i32)]
+pub enum SensorColorCode {
+    NONE = 0,
+    BLACK = 1,
+    BLUE = 2,
+    GREEN = 3,
+    YELLOW = 4,
+    RED = 5,
+    WHITE = 6,
+    BROWN = 7,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RgbRaw {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct IrSeek {
+    pub heading: [i8; 4usize],
+    pub distance: [i8; 4usize],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct IrRemote {
+    pub channel: [u8; 4usize],
+}
@@ -1,9 +1,48 @@
 #![no_std]
 
+#[cfg(target_arch = "arm")]
 pub use ev3rt;
-use ev3rt::*;
+#[cfg(target_arch = "arm")]
+pub use ev3rt::{
+    Button, IrRemote, IrSeek, LcdColor, LcdFont, LedColor, MotorPort, MotorType, RgbRaw,
+    SensorColorCode, SensorPort, SensorType, ER, LCD_HEIGHT, LCD_WIDTH,
+};
+
+#[cfg(not(target_arch = "arm"))]
+mod host;
+#[cfg(not(target_arch = "arm"))]
+pub use host::*;
+
+mod backend;
+mod color;
+mod driver;
+mod error;
+mod filter;
+mod gyro;
+mod history;
+pub mod mock;
+mod position;
+mod profile;
+mod speed;
+mod stall;
+
+pub use backend::{Backend, Ev3rt, SensorBackend};
+pub use color::{
+    ColorClassifier, ColorProfile, Hsl, Hsv, HueBand, HueMatch, HueMatcher,
+    COLOR_CALIBRATION_SAMPLES, COLOR_CLASSIFIER_BYTES, COLOR_PROFILE_BYTES, MAX_COLOR_CLASSES,
+    MAX_HUE_BANDS,
+};
+pub use driver::{DriverRef, SensorDriver};
+pub use error::{ConfigError, ConfigErrorReason, ConfigReport};
+pub use filter::{FilterChain, SensorFilter, FILTER_WINDOW, MAX_FILTERS};
+pub use gyro::{GyroTracker, GYRO_CALIBRATION_SAMPLES};
+pub use history::SensorHistory;
+pub use position::{PositionConfig, PositionZones};
+pub use profile::{MotionProfile, ProfileShape};
+pub use speed::{SpeedConfig, SpeedEstimator, SpeedRegulator, SPEED_ESTIMATOR_SAMPLES};
+pub use stall::{StallConfig, StallDetector};
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum ColorSensorMode {
     NONE = 0,
     COLOR,
@@ -12,7 +51,7 @@ pub enum ColorSensorMode {
     RGB,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum IrSensorMode {
     NONE = 0,
     DISTANCE,
@@ -20,20 +59,25 @@ pub enum IrSensorMode {
     REMOTE,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum UsSensorMode {
     NONE = 0,
     DISTANCE,
+    // another ultrasonic sensor is pinging nearby (EV3 only)
+    LISTEN,
+    // one measurement for every SensorData::trigger_measurement(), the sensor
+    // stays silent in between (EV3 only: an NXT sensor keeps pinging)
+    SINGLE,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum GyroSensorMode {
     NONE = 0,
     ANGLE,
     RATE,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum SensorConfiguration {
     None,
     Ultrasonic(UsSensorMode),
@@ -45,29 +89,412 @@ pub enum SensorConfiguration {
     NxtColor,
     NxtTemp,
     NxtUltrasonic(UsSensorMode),
+    Custom(DriverRef),
+}
+
+impl SensorConfiguration {
+    pub fn sensor_type(&self) -> SensorType {
+        match self {
+            SensorConfiguration::None => SensorType::NONE,
+            SensorConfiguration::Ultrasonic(_) => SensorType::ULTRASONIC,
+            SensorConfiguration::Gyro(_) => SensorType::GYRO,
+            SensorConfiguration::Touch => SensorType::TOUCH,
+            SensorConfiguration::Color(_) => SensorType::COLOR,
+            SensorConfiguration::Ir(_) => SensorType::INFRARED,
+            SensorConfiguration::NxtAccel => SensorType::HtNxtACCEL,
+            SensorConfiguration::NxtColor => SensorType::HtNxtCOLOR,
+            SensorConfiguration::NxtTemp => SensorType::NxtTEMP,
+            SensorConfiguration::Custom(driver) => driver.driver().sensor_type(),
+            SensorConfiguration::NxtUltrasonic(_) => SensorType::NxtULTRASONIC,
+        }
+    }
+
+    // How often SensorData::read polls the hardware, unless changed with
+    // SensorData::set_read_period. NXT I2C sensors cannot keep up with every
+    // loop, and the others are not slowed down for them.
+    pub fn default_read_period(&self) -> Duration {
+        match self {
+            SensorConfiguration::NxtUltrasonic(_) => Duration::from_usec(23456),
+            SensorConfiguration::NxtAccel | SensorConfiguration::NxtColor => {
+                Duration::from_msec(10)
+            }
+            SensorConfiguration::NxtTemp => Duration::from_msec(100),
+            SensorConfiguration::Custom(driver) => driver.driver().read_period(),
+            _ => Duration::zero(),
+        }
+    }
+
+    // Whether SensorData::detect can find this sensor. Gyro and touch
+    // sensors read just like an empty port (ev3rt reports the configured
+    // type, not the plugged one), so they never are.
+    pub fn is_detectable(&self) -> bool {
+        DETECT_ORDER.contains(&self.sensor_type())
+    }
+
+    // Whether an unplugged sensor can be told from a working one: only
+    // modes with an invalid reading can. ev3rt keeps reporting the configured
+    // type, and gyro, touch, color codes, IR beacons and remote, and listen
+    // flags read as plausible values with nothing attached.
+    pub fn shows_unplug(&self) -> bool {
+        matches!(
+            self,
+            SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE | UsSensorMode::SINGLE)
+                | SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE)
+                | SensorConfiguration::Color(
+                    ColorSensorMode::REFLECT | ColorSensorMode::AMBIENT | ColorSensorMode::RGB
+                )
+                | SensorConfiguration::Ir(IrSensorMode::DISTANCE)
+                | SensorConfiguration::NxtAccel
+                | SensorConfiguration::NxtColor
+                | SensorConfiguration::NxtTemp
+                | SensorConfiguration::Custom(_)
+        )
+    }
+
+    // Data channels holding a measured quantity, the ones filters work on.
+    // Color codes, IR beacons and remote buttons, touch and listen flags
+    // are not.
+    pub fn scalar_channels(&self) -> [bool; 4] {
+        match self {
+            SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE | UsSensorMode::SINGLE)
+            | SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE)
+            | SensorConfiguration::Color(ColorSensorMode::REFLECT | ColorSensorMode::AMBIENT)
+            | SensorConfiguration::Ir(IrSensorMode::DISTANCE)
+            | SensorConfiguration::Gyro(GyroSensorMode::ANGLE | GyroSensorMode::RATE)
+            | SensorConfiguration::NxtTemp => [true, false, false, false],
+            SensorConfiguration::Color(ColorSensorMode::RGB) | SensorConfiguration::NxtAccel => {
+                [true, true, true, false]
+            }
+            SensorConfiguration::NxtColor => [false, true, true, true],
+            SensorConfiguration::Custom(_) => [true; 4],
+            _ => [false; 4],
+        }
+    }
+
+    // The configuration used for a detected sensor when none was requested
+    pub fn default_for(st: SensorType) -> SensorConfiguration {
+        match st {
+            SensorType::NONE => SensorConfiguration::None,
+            SensorType::ULTRASONIC => SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE),
+            SensorType::GYRO => SensorConfiguration::Gyro(GyroSensorMode::ANGLE),
+            SensorType::TOUCH => SensorConfiguration::Touch,
+            SensorType::COLOR => SensorConfiguration::Color(ColorSensorMode::REFLECT),
+            SensorType::INFRARED => SensorConfiguration::Ir(IrSensorMode::DISTANCE),
+            SensorType::HtNxtACCEL => SensorConfiguration::NxtAccel,
+            SensorType::HtNxtCOLOR => SensorConfiguration::NxtColor,
+            SensorType::NxtULTRASONIC => SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE),
+            SensorType::NxtTEMP => SensorConfiguration::NxtTemp,
+        }
+    }
+}
+
+// Sensors that give themselves away with a reading an empty port cannot
+// produce: EV3 (UART) ones first, then the NXT (I2C) ones
+const DETECT_ORDER: [SensorType; 7] = [
+    SensorType::ULTRASONIC,
+    SensorType::COLOR,
+    SensorType::INFRARED,
+    SensorType::NxtULTRASONIC,
+    SensorType::HtNxtACCEL,
+    SensorType::HtNxtCOLOR,
+    SensorType::NxtTEMP,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    Unknown = 0,
+    Black,
+    Blue,
+    Green,
+    Yellow,
+    Red,
+    White,
+    Brown,
+}
+
+impl Color {
+    pub fn from_code(code: SensorColorCode) -> Color {
+        match code {
+            SensorColorCode::NONE => Color::Unknown,
+            SensorColorCode::BLACK => Color::Black,
+            SensorColorCode::BLUE => Color::Blue,
+            SensorColorCode::GREEN => Color::Green,
+            SensorColorCode::YELLOW => Color::Yellow,
+            SensorColorCode::RED => Color::Red,
+            SensorColorCode::WHITE => Color::White,
+            SensorColorCode::BROWN => Color::Brown,
+        }
+    }
+
+    fn from_data(v: u16) -> Color {
+        match v {
+            1 => Color::Black,
+            2 => Color::Blue,
+            3 => Color::Green,
+            4 => Color::Yellow,
+            5 => Color::Red,
+            6 => Color::White,
+            7 => Color::Brown,
+            _ => Color::Unknown,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Unknown => "UNKNOWN",
+            Color::Black => "BLACK",
+            Color::Blue => "BLUE",
+            Color::Green => "GREEN",
+            Color::Yellow => "YELLOW",
+            Color::Red => "RED",
+            Color::White => "WHITE",
+            Color::Brown => "BROWN",
+        }
+    }
 }
 
-pub struct SensorData {
+#[derive(Clone, Copy, PartialEq)]
+pub struct IrBeacon {
+    // -25 (left) to 25 (right)
+    pub heading: i32,
+    // 0-100
+    pub distance: i32,
+}
+
+impl IrBeacon {
+    fn encode(heading: i8, distance: i8) -> u16 {
+        ((heading as u8 as u16) << 8) | (distance as u8 as u16)
+    }
+
+    fn decode(v: u16) -> Option<IrBeacon> {
+        let heading = (v >> 8) as u8 as i8;
+        let distance = v as u8 as i8;
+        if distance == -128 {
+            None
+        } else {
+            Some(IrBeacon {
+                heading: heading.into(),
+                distance: distance.into(),
+            })
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct IrRemoteButtons {
+    pub red_up: bool,
+    pub red_down: bool,
+    pub blue_up: bool,
+    pub blue_down: bool,
+    pub beacon: bool,
+}
+
+impl IrRemoteButtons {
+    const RED_UP: u16 = 0x01;
+    const RED_DOWN: u16 = 0x02;
+    const BLUE_UP: u16 = 0x04;
+    const BLUE_DOWN: u16 = 0x08;
+    const BEACON: u16 = 0x10;
+
+    fn decode(v: u16) -> IrRemoteButtons {
+        IrRemoteButtons {
+            red_up: v & Self::RED_UP != 0,
+            red_down: v & Self::RED_DOWN != 0,
+            blue_up: v & Self::BLUE_UP != 0,
+            blue_down: v & Self::BLUE_DOWN != 0,
+            beacon: v & Self::BEACON != 0,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.red_up || self.red_down || self.blue_up || self.blue_down || self.beacon
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SensorReading {
+    // mm
+    Distance(i32),
+    // another ultrasonic sensor heard
+    Listen(bool),
+    // 0-100
+    Proximity(i32),
+    // 0-100
+    Ambient(i32),
+    // 0-100
+    Reflect(i32),
+    Color(Color),
+    Rgb { r: i32, g: i32, b: i32 },
+    // channels 1-4, None when that beacon is not in sight
+    IrSeek([Option<IrBeacon>; 4]),
+    // channels 1-4
+    IrRemote([IrRemoteButtons; 4]),
+    Touch(bool),
+    // deg
+    GyroAngle(i32),
+    // deg/s
+    GyroRate(i32),
+    // raw HiTechnic units, about 200 per g
+    Acceleration { x: i32, y: i32, z: i32 },
+    // HiTechnic color number (0-17), rgb 0-255
+    NxtColor { color: i32, r: i32, g: i32, b: i32 },
+    // 1/100 deg C
+    Temperature(i32),
+    // whatever a SensorDriver reports
+    Custom([i32; 4]),
+}
+
+// Actual timing of the hardware reads of a sensor
+#[derive(Clone, Copy)]
+pub struct ReadStats {
+    reads: u32,
+    total_usec: i64,
+    last_period: Duration,
+    min_period: Duration,
+    max_period: Duration,
+}
+
+impl ReadStats {
+    pub fn new() -> Self {
+        Self {
+            reads: 0,
+            total_usec: 0,
+            last_period: Duration::zero(),
+            min_period: Duration::zero(),
+            max_period: Duration::zero(),
+        }
+    }
+
+    pub fn update(&mut self, period: Duration) {
+        if self.reads == 0 || period < self.min_period {
+            self.min_period = period;
+        }
+        if self.reads == 0 || period > self.max_period {
+            self.max_period = period;
+        }
+        self.reads += 1;
+        self.total_usec += period.usec() as i64;
+        self.last_period = period;
+    }
+
+    pub fn reads(&self) -> u32 {
+        self.reads
+    }
+
+    pub fn last_period(&self) -> Duration {
+        self.last_period
+    }
+
+    pub fn min_period(&self) -> Duration {
+        self.min_period
+    }
+
+    pub fn max_period(&self) -> Duration {
+        self.max_period
+    }
+
+    pub fn average_period(&self) -> Duration {
+        if self.reads == 0 {
+            return Duration::zero();
+        }
+        Duration::from_usec((self.total_usec / self.reads as i64) as i32)
+    }
+
+    // reads per second
+    pub fn rate(&self) -> i32 {
+        if self.total_usec == 0 {
+            return 0;
+        }
+        (self.reads as i64 * 1_000_000 / self.total_usec) as i32
+    }
+}
+
+impl Default for ReadStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A configured sensor without valid readings for this long is considered
+// unplugged (only in modes where SensorConfiguration::shows_unplug); after
+// that its configuration is retried every SENSOR_RETRY_PERIOD.
+pub const SENSOR_LOST_TIMEOUT: Duration = Duration::from_msec(500);
+pub const SENSOR_RETRY_PERIOD: Duration = Duration::from_msec(1000);
+// After SensorData::switch_mode, readings are ignored for at least this long:
+// the sensor may still answer in the old mode.
+pub const SENSOR_MODE_SETTLE_TIME: Duration = Duration::from_msec(50);
+// Default for SensorData::set_stale_threshold, above every default read period
+pub const SENSOR_STALE_TIME: Duration = Duration::from_msec(250);
+// Ev3::apply_configuration gives up on sensors that still fail after this
+// long and reports their last error
+pub const SENSOR_CONFIG_TIMEOUT: Duration = Duration::from_msec(10_000);
+
+pub struct SensorData<B = Ev3rt> {
+    hw: B,
     port_idx: SensorPort,
     cfg: SensorConfiguration,
     cfg_applied: bool,
     minimum_read_period: Duration,
     elapsed_read_period: Duration,
+    read_stats: ReadStats,
+    age: Option<Duration>,
+    stale_threshold: Duration,
+    valid: bool,
+    // time since the single shot measurement started
+    us_trigger: Option<Duration>,
+    us_shot: Option<i16>,
+    settling: Option<Duration>,
+    mode_ready_event: bool,
+    lost: bool,
+    silent_for: Duration,
+    connect_event: bool,
+    disconnect_event: bool,
+    touch: KeyStatus,
+    filters: FilterChain,
+    filtered: Option<[i32; 4]>,
+    gyro: GyroTracker,
+    color_profile: ColorProfile,
     pub data: [u16; 4],
 }
 
+#[cfg(target_arch = "arm")]
 impl SensorData {
+    pub fn new(port: SensorPort) -> Self {
+        Self::with_backend(port, Ev3rt)
+    }
+}
+
+impl<B: Backend> SensorData<B> {
     fn clear_data(&mut self) {
         self.data = [0xffff; 4];
+        self.valid = false;
     }
 
-    pub fn new(port: SensorPort) -> Self {
+    pub fn with_backend(port: SensorPort, hw: B) -> Self {
         Self {
+            hw,
             port_idx: port,
             cfg: SensorConfiguration::None,
             cfg_applied: false,
             minimum_read_period: Duration::zero(),
             elapsed_read_period: Duration::zero(),
+            read_stats: ReadStats::new(),
+            age: None,
+            stale_threshold: SENSOR_STALE_TIME,
+            valid: false,
+            us_trigger: None,
+            us_shot: None,
+            settling: None,
+            mode_ready_event: false,
+            lost: false,
+            silent_for: Duration::zero(),
+            connect_event: false,
+            disconnect_event: false,
+            touch: KeyStatus::new(),
+            filters: FilterChain::new(),
+            filtered: None,
+            gyro: GyroTracker::new(),
+            color_profile: ColorProfile::new(),
             data: [0xffff; 4],
         }
     }
@@ -99,302 +526,896 @@ impl SensorData {
         Self::val_conversion(self.data[3])
     }
 
+    // None when the last read produced no value (or nothing was read yet)
+    pub fn reading(&self) -> Option<SensorReading> {
+        if !self.valid {
+            return None;
+        }
+        self.decode(&self.data)
+    }
+
+    // Time since the last hardware read that gave a valid value (None if
+    // there was none since the sensor was configured)
+    pub fn age(&self) -> Option<Duration> {
+        self.age
+    }
+
+    pub fn reading_with_age(&self) -> Option<(SensorReading, Duration)> {
+        Some((self.reading()?, self.age?))
+    }
+
+    pub fn stale_threshold(&self) -> Duration {
+        self.stale_threshold
+    }
+
+    pub fn set_stale_threshold(&mut self, threshold: Duration) {
+        self.stale_threshold = threshold;
+    }
+
+    // No valid hardware read for longer than the stale threshold
+    pub fn is_stale(&self) -> bool {
+        match self.age {
+            Some(age) => age > self.stale_threshold,
+            None => true,
+        }
+    }
+
+    // Like reading(), after the filters set with set_filters()
+    pub fn filtered_reading(&self) -> Option<SensorReading> {
+        let filtered = self.filtered?;
+        self.decode(&[
+            filtered[0] as u16,
+            filtered[1] as u16,
+            filtered[2] as u16,
+            filtered[3] as u16,
+        ])
+    }
+
+    pub fn filtered_val(&self) -> Option<i32> {
+        self.filtered.map(|f| f[0])
+    }
+
+    pub fn filtered(&self) -> Option<[i32; 4]> {
+        self.filtered
+    }
+
+    // Only channels with plain numbers are filtered, see
+    // SensorConfiguration::scalar_channels
+    pub fn set_filters(&mut self, filters: &[SensorFilter]) {
+        self.filters.set(filters);
+        self.filtered = None;
+    }
+
+    pub fn filters(&self) -> &[SensorFilter] {
+        self.filters.filters()
+    }
+
+    fn decode(&self, data: &[u16; 4]) -> Option<SensorReading> {
+        let v = Self::val_conversion(data[0]);
+        match &self.cfg {
+            SensorConfiguration::Touch => Some(SensorReading::Touch(v != 0)),
+            SensorConfiguration::Color(mode) => match mode {
+                ColorSensorMode::AMBIENT => Some(SensorReading::Ambient(v)),
+                ColorSensorMode::REFLECT => Some(SensorReading::Reflect(v)),
+                ColorSensorMode::COLOR => Some(SensorReading::Color(Color::from_data(data[0]))),
+                ColorSensorMode::RGB => Some(SensorReading::Rgb {
+                    r: data[0] as i32,
+                    g: data[1] as i32,
+                    b: data[2] as i32,
+                }),
+                _ => None,
+            },
+            SensorConfiguration::Ir(mode) => match mode {
+                IrSensorMode::DISTANCE => Some(SensorReading::Proximity(v)),
+                IrSensorMode::SEEK => Some(SensorReading::IrSeek([
+                    IrBeacon::decode(data[0]),
+                    IrBeacon::decode(data[1]),
+                    IrBeacon::decode(data[2]),
+                    IrBeacon::decode(data[3]),
+                ])),
+                IrSensorMode::REMOTE => Some(SensorReading::IrRemote([
+                    IrRemoteButtons::decode(data[0]),
+                    IrRemoteButtons::decode(data[1]),
+                    IrRemoteButtons::decode(data[2]),
+                    IrRemoteButtons::decode(data[3]),
+                ])),
+                _ => None,
+            },
+            SensorConfiguration::Ultrasonic(mode) | SensorConfiguration::NxtUltrasonic(mode) => {
+                match mode {
+                    UsSensorMode::DISTANCE | UsSensorMode::SINGLE => {
+                        Some(SensorReading::Distance(v * 10))
+                    }
+                    UsSensorMode::LISTEN => Some(SensorReading::Listen(v != 0)),
+                    UsSensorMode::NONE => None,
+                }
+            }
+            SensorConfiguration::Gyro(mode) => match mode {
+                GyroSensorMode::ANGLE => Some(SensorReading::GyroAngle(v)),
+                GyroSensorMode::RATE => Some(SensorReading::GyroRate(v)),
+                GyroSensorMode::NONE => None,
+            },
+            SensorConfiguration::NxtAccel => Some(SensorReading::Acceleration {
+                x: Self::val_conversion(data[0]),
+                y: Self::val_conversion(data[1]),
+                z: Self::val_conversion(data[2]),
+            }),
+            SensorConfiguration::NxtColor => Some(SensorReading::NxtColor {
+                color: v,
+                r: data[1] as i32,
+                g: data[2] as i32,
+                b: data[3] as i32,
+            }),
+            SensorConfiguration::NxtTemp => Some(SensorReading::Temperature(v)),
+            SensorConfiguration::Custom(_) => Some(SensorReading::Custom([
+                Self::val_conversion(data[0]),
+                Self::val_conversion(data[1]),
+                Self::val_conversion(data[2]),
+                Self::val_conversion(data[3]),
+            ])),
+            _ => None,
+        }
+    }
+
+    // Some(Color::Unknown) means the sensor sees no known color,
+    // None that there is no color reading at all
+    pub fn color(&self) -> Option<Color> {
+        match self.reading() {
+            Some(SensorReading::Color(c)) => Some(c),
+            _ => None,
+        }
+    }
+
+    // Press and release events of a touch sensor, like a brick button
+    pub fn touch(&self) -> &KeyStatus {
+        &self.touch
+    }
+
+    // Bias and integrated heading of a gyro in RATE mode
+    pub fn gyro(&self) -> &GyroTracker {
+        &self.gyro
+    }
+
+    pub fn gyro_mut(&mut self) -> &mut GyroTracker {
+        &mut self.gyro
+    }
+
+    // Calibration profile of a color sensor, kept across configure() so that
+    // the same references serve REFLECT and RGB
+    pub fn color_profile(&self) -> ColorProfile {
+        self.color_profile
+    }
+
+    pub fn set_color_profile(&mut self, profile: ColorProfile) {
+        self.color_profile = profile;
+    }
+
+    // Records the black reference, with the sensor in REFLECT mode over a
+    // black surface. Returns false if there was nothing to record.
+    pub fn calibrate_black(&mut self) -> bool {
+        match (&self.cfg, self.color_sample()) {
+            (SensorConfiguration::Color(ColorSensorMode::REFLECT), Some(v)) => {
+                self.color_profile.black = v[0];
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Records the white reference for the current mode (REFLECT or RGB),
+    // with the sensor over a white surface.
+    pub fn calibrate_white(&mut self) -> bool {
+        match (&self.cfg, self.color_sample()) {
+            (SensorConfiguration::Color(ColorSensorMode::REFLECT), Some(v)) => {
+                self.color_profile.white = v[0];
+                true
+            }
+            (SensorConfiguration::Color(ColorSensorMode::RGB), Some(v)) => {
+                self.color_profile.white_rgb = v;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn color_sample(&self) -> Option<[i32; 3]> {
+        if !self.cfg_applied {
+            return None;
+        }
+        let mut sum = [0; 3];
+        for _ in 0..COLOR_CALIBRATION_SAMPLES {
+            match &self.cfg {
+                SensorConfiguration::Color(ColorSensorMode::REFLECT) => {
+                    let val = self.hw.color_sensor_get_reflect(self.port_idx);
+                    if val == 0xff {
+                        return None;
+                    }
+                    sum[0] += val as i32;
+                }
+                SensorConfiguration::Color(ColorSensorMode::RGB) => {
+                    let val = self.hw.color_sensor_get_rgb(self.port_idx);
+                    if val.r == 0xffff || val.g == 0xffff || val.b == 0xffff {
+                        return None;
+                    }
+                    sum[0] += val.r as i32;
+                    sum[1] += val.g as i32;
+                    sum[2] += val.b as i32;
+                }
+                _ => return None,
+            }
+            self.hw.msleep(2);
+        }
+        Some([
+            sum[0] / COLOR_CALIBRATION_SAMPLES,
+            sum[1] / COLOR_CALIBRATION_SAMPLES,
+            sum[2] / COLOR_CALIBRATION_SAMPLES,
+        ])
+    }
+
+    // Filtered REFLECT value scaled with the profile: 0 black, 1000 white
+    pub fn normalized_reflect(&self) -> Option<i32> {
+        match self.filtered_reading() {
+            Some(SensorReading::Reflect(v)) => Some(self.color_profile.normalize_reflect(v)),
+            _ => None,
+        }
+    }
+
+    // Filtered RGB values white balanced with the profile, 0-1000 each
+    pub fn balanced_rgb(&self) -> Option<(i32, i32, i32)> {
+        match self.filtered_reading() {
+            Some(SensorReading::Rgb { r, g, b }) => Some(self.color_profile.balance_rgb(r, g, b)),
+            _ => None,
+        }
+    }
+
+    // Adds the current balanced RGB value as a sample of label.
+    // False without an RGB reading, or if the classifier is full.
+    pub fn train_color(&self, classifier: &mut ColorClassifier, label: u8) -> bool {
+        match self.balanced_rgb() {
+            Some(rgb) => classifier.add_sample(label, rgb),
+            None => false,
+        }
+    }
+
+    pub fn classify_color(&self, classifier: &ColorClassifier) -> Option<u8> {
+        classifier.classify(self.balanced_rgb()?)
+    }
+
+    pub fn hsv(&self) -> Option<Hsv> {
+        Some(Hsv::from_rgb(self.balanced_rgb()?))
+    }
+
+    pub fn hsl(&self) -> Option<Hsl> {
+        Some(Hsl::from_rgb(self.balanced_rgb()?))
+    }
+
+    pub fn match_hue(&self, matcher: &HueMatcher) -> Option<HueMatch> {
+        matcher.find(self.hsv()?)
+    }
+
+    // channel is 1-4, as printed on the beacon
+    pub fn ir_beacon(&self, channel: usize) -> Option<IrBeacon> {
+        match self.reading() {
+            Some(SensorReading::IrSeek(beacons)) if (1..=4).contains(&channel) => {
+                beacons[channel - 1]
+            }
+            _ => None,
+        }
+    }
+
+    // channel is 1-4, as selected on the remote
+    pub fn ir_remote(&self, channel: usize) -> Option<IrRemoteButtons> {
+        match self.reading() {
+            Some(SensorReading::IrRemote(buttons)) if (1..=4).contains(&channel) => {
+                Some(buttons[channel - 1])
+            }
+            _ => None,
+        }
+    }
+
+    pub fn configuration(&self) -> SensorConfiguration {
+        self.cfg
+    }
+
     pub fn configuration_applied(&self) -> bool {
         self.cfg_applied
     }
 
+    // True while the sensor has stopped answering since its configuration
+    // was applied (see SensorConfiguration::shows_unplug), or switch_mode
+    // could not apply it: read() keeps trying to apply it again.
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    // Set by the read() that brought a lost sensor back
+    pub fn connect_event(&self) -> bool {
+        self.connect_event
+    }
+
+    // Set by the read() that found the sensor gone
+    pub fn disconnect_event(&self) -> bool {
+        self.disconnect_event
+    }
+
+    fn disconnect(&mut self) {
+        self.clear_data();
+        self.cfg_applied = false;
+        self.lost = true;
+        self.settling = None;
+        self.age = None;
+        self.silent_for = Duration::zero();
+        self.disconnect_event = true;
+        self.touch = KeyStatus::new();
+        self.filters.reset();
+        self.filtered = None;
+    }
+
     pub fn configure(&mut self, cfg: SensorConfiguration) {
         self.cfg = cfg;
         self.cfg_applied = false;
-        match self.cfg {
-            SensorConfiguration::NxtUltrasonic(_) => {
-                self.minimum_read_period = Duration::from_usec(23456)
-            }
-            _ => {}
+        self.lost = false;
+        self.us_trigger = None;
+        self.us_shot = None;
+        self.settling = None;
+        self.age = None;
+        self.touch = KeyStatus::new();
+        self.filters.reset();
+        self.filtered = None;
+        self.gyro = GyroTracker::new();
+        self.minimum_read_period = cfg.default_read_period();
+        self.elapsed_read_period = Duration::zero();
+        self.read_stats = ReadStats::new();
+        self.clear_data();
+    }
+
+    // Changes the configuration of a working sensor without blocking.
+    // Another mode of the same sensor takes effect at once, but readings stay
+    // None while the sensor settles (see is_settling and mode_ready_event);
+    // anything else goes through configure and one attempt_cfg_apply, and if
+    // that fails read() keeps retrying (as for a lost sensor).
+    pub fn switch_mode(&mut self, cfg: SensorConfiguration) -> Result<(), ConfigError> {
+        if !self.cfg_applied || cfg.sensor_type() != self.cfg.sensor_type() {
+            self.configure(cfg);
+            let result = self.attempt_cfg_apply();
+            self.lost = result.is_err();
+            return result;
+        }
+
+        let old = self.cfg;
+        self.cfg = cfg;
+        // the sensor is not settled yet, so only the mode itself is checked
+        if let Some(ConfigErrorReason::UnsupportedMode) = self.check_first_reading() {
+            self.cfg = old;
+            return Err(ConfigError::Sensor {
+                port: self.port(),
+                cfg,
+                reason: ConfigErrorReason::UnsupportedMode,
+            });
         }
+
         self.clear_data();
+        self.us_trigger = None;
+        self.us_shot = None;
+        self.filters.reset();
+        self.filtered = None;
+        // the gyro bias stays good across modes (the heading is only
+        // integrated in RATE though)
+        self.silent_for = Duration::zero();
+        self.settling = Some(Duration::zero());
+        self.age = None;
+        Ok(())
+    }
+
+    // True from switch_mode until the first valid reading in the new mode
+    pub fn is_settling(&self) -> bool {
+        self.settling.is_some()
     }
 
-    pub fn attempt_cfg_apply(&mut self) -> bool {
+    // Set by the read() that produced the first valid reading after
+    // switch_mode
+    pub fn mode_ready_event(&self) -> bool {
+        self.mode_ready_event
+    }
+
+    pub fn attempt_cfg_apply(&mut self) -> Result<(), ConfigError> {
         self.clear_data();
         self.cfg_applied = false;
+        self.age = None;
 
-        match &self.cfg {
-            SensorConfiguration::None => {
-                ev3rt::sensor_config(self.port(), SensorType::NONE);
+        if self.cfg == SensorConfiguration::None {
+            self.hw.sensor_config(self.port(), SensorType::NONE);
+            self.cfg_applied = true;
+            return Ok(());
+        }
+
+        let st = self.cfg.sensor_type();
+        let er = self.hw.sensor_config(self.port(), st);
+        // ev3rt accepts any type and sensor_get_type just echoes it back:
+        // a missing or different sensor only shows in its first reading
+        let failure = if er != ER::OK {
+            Some(ConfigErrorReason::Driver(er))
+        } else {
+            self.check_first_reading()
+        };
+
+        match failure {
+            None => {
                 self.cfg_applied = true;
+                self.silent_for = Duration::zero();
+                self.elapsed_read_period = Duration::zero();
+                Ok(())
             }
-            SensorConfiguration::Ultrasonic(mode) => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::ULTRASONIC);
-                if er == ER::OK {
-                    self.cfg_applied = true;
-                    match mode {
-                        UsSensorMode::DISTANCE => {
-                            let val = ev3rt::ultrasonic_sensor_get_distance(self.port_idx);
-                            if val == -1 || val == 0 {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        _ => {
-                            self.cfg_applied = false;
-                        }
+            Some(reason) => Err(ConfigError::Sensor {
+                port: self.port(),
+                cfg: self.cfg,
+                reason,
+            }),
+        }
+    }
+
+    fn check_first_reading(&self) -> Option<ConfigErrorReason> {
+        match &self.cfg {
+            SensorConfiguration::Ultrasonic(mode) => match mode {
+                UsSensorMode::DISTANCE => {
+                    let val = self.hw.ultrasonic_sensor_get_distance(self.port_idx);
+                    if val == -1 || val == 0 {
+                        return Some(ConfigErrorReason::InvalidReading);
                     }
                 }
-            }
-            SensorConfiguration::Gyro(mode) => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::GYRO);
-                if er == ER::OK {
-                    self.cfg_applied = true;
-                    match mode {
-                        _ => {
-                            self.cfg_applied = true;
-                        }
+                UsSensorMode::LISTEN | UsSensorMode::SINGLE => {
+                    // leaves the sensor quiet until it is asked to measure
+                    self.hw.ultrasonic_sensor_listen(self.port_idx);
+                }
+                UsSensorMode::NONE => return Some(ConfigErrorReason::UnsupportedMode),
+            },
+            SensorConfiguration::Color(mode) => match mode {
+                ColorSensorMode::AMBIENT => {
+                    let val = self.hw.color_sensor_get_ambient(self.port_idx);
+                    if val == 0xff || val == 0 {
+                        return Some(ConfigErrorReason::InvalidReading);
                     }
                 }
-            }
-            SensorConfiguration::Touch => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::TOUCH);
-                if er == ER::OK {
-                    self.cfg_applied = true;
+                ColorSensorMode::COLOR => {
+                    let val = self.hw.color_sensor_get_color(self.port_idx);
+                    if let SensorColorCode::NONE = val {
+                        return Some(ConfigErrorReason::InvalidReading);
+                    }
                 }
-                self.cfg_applied = false;
-            }
-            SensorConfiguration::Color(mode) => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::COLOR);
-                if er == ER::OK {
-                    self.cfg_applied = true;
-                    match mode {
-                        ColorSensorMode::AMBIENT => {
-                            let val = ev3rt::color_sensor_get_ambient(self.port_idx);
-                            if val == 0xff || val == 0 {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        ColorSensorMode::COLOR => {
-                            let val = ev3rt::color_sensor_get_color(self.port_idx);
-                            if let ev3rt::SensorColorCode::NONE = val {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        ColorSensorMode::REFLECT => {
-                            let val = ev3rt::color_sensor_get_reflect(self.port_idx);
-                            if val == 0xff || val == 0 {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        ColorSensorMode::RGB => {
-                            let val = ev3rt::color_sensor_get_rgb(self.port_idx);
-                            if val.r == 0xffff
-                                || val.g == 0xffff
-                                || val.b == 0xffff
-                                || (val.r == 0 && val.g == 0 && val.b == 0)
-                            {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        _ => {}
+                ColorSensorMode::REFLECT => {
+                    let val = self.hw.color_sensor_get_reflect(self.port_idx);
+                    if val == 0xff || val == 0 {
+                        return Some(ConfigErrorReason::InvalidReading);
                     }
                 }
-            }
-            SensorConfiguration::Ir(mode) => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::INFRARED);
-                if er == ER::OK {
-                    self.cfg_applied = true;
-                    match mode {
-                        IrSensorMode::DISTANCE => {
-                            let val = ev3rt::infrared_sensor_get_distance(self.port_idx);
-                            if val == 0xff || val == 0 {
-                                self.cfg_applied = false;
-                            }
-                        }
-                        IrSensorMode::REMOTE => {
-                            self.cfg_applied = false;
-                        }
-                        IrSensorMode::SEEK => {
-                            self.cfg_applied = false;
-                        }
-                        _ => {}
+                ColorSensorMode::RGB => {
+                    let val = self.hw.color_sensor_get_rgb(self.port_idx);
+                    if val.r == 0xffff
+                        || val.g == 0xffff
+                        || val.b == 0xffff
+                        || (val.r == 0 && val.g == 0 && val.b == 0)
+                    {
+                        return Some(ConfigErrorReason::InvalidReading);
                     }
                 }
+                _ => {}
+            },
+            SensorConfiguration::Ir(IrSensorMode::DISTANCE) => {
+                let val = self.hw.infrared_sensor_get_distance(self.port_idx);
+                if val == 0xff || val == 0 {
+                    return Some(ConfigErrorReason::InvalidReading);
+                }
             }
-            SensorConfiguration::NxtAccel => {}
-            SensorConfiguration::NxtColor => {}
-            SensorConfiguration::NxtTemp => {}
-            SensorConfiguration::NxtUltrasonic(mode) => {
-                let er = ev3rt::sensor_config(self.port(), SensorType::NxtULTRASONIC);
-                if er == ER::OK {
-                    self.cfg_applied = true;
-                    match mode {
-                        UsSensorMode::DISTANCE => {
-                            /*
-                            let val = ev3rt::ultrasonic_sensor_get_distance_nxt(self.port_idx);
-                            if val == -1 || val == 0 {
-                                self.cfg_applied = false;
-                            }
-                            */
-                        }
-                        _ => {
-                            self.cfg_applied = false;
-                        }
+            SensorConfiguration::NxtUltrasonic(mode) => match mode {
+                UsSensorMode::DISTANCE => {
+                    // a missing sensor does not answer on I2C
+                    let val = self.hw.ultrasonic_sensor_get_distance_nxt(self.port_idx);
+                    if val.is_none() {
+                        return Some(ConfigErrorReason::InvalidReading);
                     }
                 }
+                _ => return Some(ConfigErrorReason::UnsupportedMode),
+            },
+            SensorConfiguration::Custom(driver) => {
+                let ok = driver.driver().init(&self.hw, self.port_idx);
+                if !ok {
+                    return Some(ConfigErrorReason::InvalidReading);
+                }
             }
+            _ => {}
         }
-        self.cfg_applied
+        None
+    }
+
+    // Probes the port for a detectable sensor (see
+    // SensorConfiguration::is_detectable), leaving the configuration
+    // untouched (but no longer applied). NONE if none answers.
+    pub fn detect(&mut self) -> SensorType {
+        self.clear_data();
+        self.cfg_applied = false;
+        self.lost = false;
+        for st in DETECT_ORDER.iter() {
+            if self.probe(*st) {
+                return *st;
+            }
+        }
+        self.hw.sensor_config(self.port(), SensorType::NONE);
+        SensorType::NONE
+    }
+
+    // ev3rt just echoes back the configured type: the sensor has to answer
+    // with something an empty port does not read
+    fn probe(&self, st: SensorType) -> bool {
+        if self.hw.sensor_config(self.port(), st) != ER::OK {
+            return false;
+        }
+        match st {
+            SensorType::ULTRASONIC => {
+                let val = self.hw.ultrasonic_sensor_get_distance(self.port_idx);
+                val != -1 && val != 0
+            }
+            SensorType::COLOR => {
+                let val = self.hw.color_sensor_get_reflect(self.port_idx);
+                val != 0xff && val != 0
+            }
+            SensorType::INFRARED => {
+                let val = self.hw.infrared_sensor_get_distance(self.port_idx);
+                val != 0xff && val != 0
+            }
+            SensorType::NxtULTRASONIC => self
+                .hw
+                .ultrasonic_sensor_get_distance_nxt(self.port_idx)
+                .is_some(),
+            SensorType::HtNxtACCEL => self.hw.ht_nxt_accel_sensor_measure(self.port_idx).is_some(),
+            SensorType::HtNxtCOLOR => self
+                .hw
+                .ht_nxt_color_sensor_measure_color(self.port_idx)
+                .is_some(),
+            SensorType::NxtTEMP => self.hw.nxt_temp_sensor_measure(self.port_idx).is_some(),
+            _ => false,
+        }
+    }
+
+    // Minimum time between two hardware reads (zero: at every read())
+    pub fn read_period(&self) -> Duration {
+        self.minimum_read_period
+    }
+
+    // Must come after configure(), that restores the default for the sensor
+    pub fn set_read_period(&mut self, period: Duration) {
+        self.minimum_read_period = period;
+    }
+
+    pub fn read_stats(&self) -> &ReadStats {
+        &self.read_stats
+    }
+
+    pub fn reset_read_stats(&mut self) {
+        self.read_stats = ReadStats::new();
     }
 
     pub fn skip_read(&mut self, from_last_read: Duration) {
         self.elapsed_read_period += from_last_read;
+        if let Some(age) = self.age.as_mut() {
+            *age += from_last_read;
+        }
     }
 
     pub fn read(&mut self, from_last_read: Duration) {
+        self.connect_event = false;
+        self.disconnect_event = false;
+        self.mode_ready_event = false;
+
         if !self.cfg_applied {
             self.clear_data();
+            self.filtered = None;
+            if self.lost {
+                self.silent_for += from_last_read;
+                if self.silent_for >= SENSOR_RETRY_PERIOD {
+                    self.silent_for = Duration::zero();
+                    if self.attempt_cfg_apply().is_ok() {
+                        self.lost = false;
+                        self.connect_event = true;
+                    }
+                }
+            }
             return;
         }
 
-        self.elapsed_read_period += from_last_read;
+        self.skip_read(from_last_read);
         if self.elapsed_read_period < self.minimum_read_period {
             return;
-        } else {
-            self.elapsed_read_period = Duration::zero();
         }
+        let from_last_hw_read = self.elapsed_read_period;
+        self.elapsed_read_period = Duration::zero();
+        self.read_stats.update(from_last_hw_read);
 
         self.clear_data();
 
         match &self.cfg {
             SensorConfiguration::None => {}
-            SensorConfiguration::Color(mode) => {
-                match mode {
-                    ColorSensorMode::AMBIENT => {
-                        let val = ev3rt::color_sensor_get_ambient(self.port_idx);
-                        if val == 0xff {
-                            self.data[0] = 0xffff;
-                        } else {
-                            self.data[0] = val as u16;
-                        }
-                    }
-                    ColorSensorMode::COLOR => {
-                        // let val = ev3rt::color_sensor_get_color(self.port_idx);
-                        // TODO: encode and decode value...
+            SensorConfiguration::Touch => {
+                let pressed = self.hw.touch_sensor_is_pressed(self.port_idx);
+                self.touch.update(pressed, from_last_hw_read);
+                self.data[0] = pressed as u16;
+                self.valid = true;
+            }
+            SensorConfiguration::Color(mode) => match mode {
+                ColorSensorMode::AMBIENT => {
+                    let val = self.hw.color_sensor_get_ambient(self.port_idx);
+                    if val == 0xff {
                         self.data[0] = 0xffff;
+                    } else {
+                        self.data[0] = val as u16;
+                        self.valid = true;
                     }
-                    ColorSensorMode::REFLECT => {
-                        let val = ev3rt::color_sensor_get_reflect(self.port_idx);
-                        if val == 0xff {
-                            self.data[0] = 0xffff;
-                        } else {
-                            self.data[0] = val as u16;
-                        }
-                    }
-                    ColorSensorMode::RGB => {
-                        let val = ev3rt::color_sensor_get_rgb(self.port_idx);
-                        self.data[0] = val.r;
-                        self.data[1] = val.g;
-                        self.data[2] = val.b;
+                }
+                ColorSensorMode::COLOR => {
+                    let val = self.hw.color_sensor_get_color(self.port_idx);
+                    self.data[0] = Color::from_code(val) as u16;
+                    self.valid = true;
+                }
+                ColorSensorMode::REFLECT => {
+                    let val = self.hw.color_sensor_get_reflect(self.port_idx);
+                    if val == 0xff {
+                        self.data[0] = 0xffff;
+                    } else {
+                        self.data[0] = val as u16;
+                        self.valid = true;
                     }
-                    _ => {}
                 }
-            }
+                ColorSensorMode::RGB => {
+                    let val = self.hw.color_sensor_get_rgb(self.port_idx);
+                    self.data[0] = val.r;
+                    self.data[1] = val.g;
+                    self.data[2] = val.b;
+                    self.valid = val.r != 0xffff && val.g != 0xffff && val.b != 0xffff;
+                }
+                _ => {}
+            },
             SensorConfiguration::Ir(mode) => match mode {
                 IrSensorMode::DISTANCE => {
-                    let val = ev3rt::infrared_sensor_get_distance(self.port_idx);
+                    let val = self.hw.infrared_sensor_get_distance(self.port_idx);
                     if val == 0xff {
                         self.data[0] = 0xffff;
                     } else {
                         self.data[0] = val as u16;
+                        self.valid = true;
                     }
                 }
                 IrSensorMode::REMOTE => {
-                    self.clear_data();
+                    let val = self.hw.infrared_sensor_get_remote(self.port_idx);
+                    for ch in 0..4 {
+                        self.data[ch] = val.channel[ch] as u16;
+                    }
+                    self.valid = true;
                 }
                 IrSensorMode::SEEK => {
-                    self.clear_data();
+                    let val = self.hw.infrared_sensor_seek(self.port_idx);
+                    for ch in 0..4 {
+                        self.data[ch] = IrBeacon::encode(val.heading[ch], val.distance[ch]);
+                    }
+                    self.valid = true;
                 }
                 _ => {}
             },
             SensorConfiguration::Ultrasonic(mode) => match mode {
                 UsSensorMode::DISTANCE => {
-                    let val = ev3rt::ultrasonic_sensor_get_distance(self.port_idx);
+                    let val = self.hw.ultrasonic_sensor_get_distance(self.port_idx);
                     if val == -1 {
                         self.data[0] = 0xffff;
                     } else {
                         self.data[0] = val as u16;
+                        self.valid = true;
                     }
                 }
-                _ => {}
-            },
-            SensorConfiguration::NxtUltrasonic(mode) => match mode {
-                UsSensorMode::DISTANCE => {
-                    let val = ev3rt::ultrasonic_sensor_get_distance_nxt(self.port_idx);
-                    if val == -1 {
-                        self.data[0] = 0xffff;
-                    } else {
-                        self.data[0] = val as u16;
+                UsSensorMode::LISTEN => {
+                    self.data[0] = self.hw.ultrasonic_sensor_listen(self.port_idx) as u16;
+                    self.valid = true;
+                }
+                UsSensorMode::SINGLE => {
+                    if let Some(running) = self.us_trigger {
+                        // the sensor pings from the first read on, but like
+                        // after any mode switch it takes a while to answer
+                        // in the new mode
+                        let val = self.hw.ultrasonic_sensor_get_distance(self.port_idx);
+                        if running >= SENSOR_MODE_SETTLE_TIME && val != -1 {
+                            self.us_trigger = None;
+                            // back to listening, so that the sensor stops pinging
+                            self.hw.ultrasonic_sensor_listen(self.port_idx);
+                            self.us_shot = Some(val);
+                            self.age = Some(Duration::zero());
+                        } else {
+                            self.us_trigger = Some(running + from_last_hw_read);
+                        }
                     }
+                    self.read_us_shot();
                 }
                 _ => {}
             },
+            SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE) => {
+                if let Some(val) = self.hw.ultrasonic_sensor_get_distance_nxt(self.port_idx) {
+                    self.data[0] = val as u16;
+                    self.valid = true;
+                }
+            }
+            SensorConfiguration::NxtUltrasonic(_) => {}
             SensorConfiguration::Gyro(mode) => match mode {
                 GyroSensorMode::ANGLE => {
-                    self.data[0] = ev3rt::gyro_sensor_get_angle(self.port_idx) as u16;
+                    self.data[0] = self.hw.gyro_sensor_get_angle(self.port_idx) as u16;
+                    self.valid = true;
                 }
                 GyroSensorMode::RATE => {
-                    let rate = ev3rt::gyro_sensor_get_rate(self.port_idx);
-                    let is_calibrated = self.data[2] == 0;
-                    self.data[0] = if is_calibrated {
-                        rate - (self.data[3] as i16)
-                    } else {
-                        rate
-                    } as u16;
+                    let rate = self.hw.gyro_sensor_get_rate(self.port_idx);
+                    let rate = self.gyro.update(rate, from_last_hw_read);
+                    self.data[0] = (rate / 1000) as u16;
+                    self.valid = true;
                 }
                 GyroSensorMode::NONE => {}
             },
-            _ => {}
+            SensorConfiguration::NxtAccel => {
+                if let Some(axes) = self.hw.ht_nxt_accel_sensor_measure(self.port_idx) {
+                    self.data[0] = axes[0] as u16;
+                    self.data[1] = axes[1] as u16;
+                    self.data[2] = axes[2] as u16;
+                    self.valid = true;
+                }
+            }
+            SensorConfiguration::NxtColor => {
+                let color = self.hw.ht_nxt_color_sensor_measure_color(self.port_idx);
+                let rgb = self.hw.ht_nxt_color_sensor_measure_rgb(self.port_idx);
+                if let (Some(color), Some(rgb)) = (color, rgb) {
+                    self.data[0] = color as u16;
+                    self.data[1] = rgb.r;
+                    self.data[2] = rgb.g;
+                    self.data[3] = rgb.b;
+                    self.valid = true;
+                }
+            }
+            SensorConfiguration::NxtTemp => {
+                if let Some(temp) = self.hw.nxt_temp_sensor_measure(self.port_idx) {
+                    self.data[0] = ((temp * 100.0) as i16) as u16;
+                    self.valid = true;
+                }
+            }
+            SensorConfiguration::Custom(driver) => {
+                if let Some(values) = driver.driver().read(&self.hw, self.port_idx) {
+                    for (d, v) in self.data.iter_mut().zip(values.iter()) {
+                        *d = *v as u16;
+                    }
+                    self.valid = true;
+                }
+            }
         }
-    }
 
-    pub fn hard_calibration(&mut self) {
-        match &self.cfg {
-            SensorConfiguration::Gyro(_) => {
-                ev3rt::gyro_sensor_reset(self.port_idx);
+        if let Some(settling) = self.settling {
+            let settling = settling + from_last_hw_read;
+            if settling < SENSOR_MODE_SETTLE_TIME {
+                self.clear_data();
+                self.settling = Some(settling);
+            } else if self.valid {
+                self.settling = None;
+                self.mode_ready_event = true;
             }
-            _ => {}
         }
+
+        // a single shot result is repeated at every read, but it is not new
+        if self.valid && !self.is_single_shot() {
+            self.age = Some(Duration::zero());
+        }
+
+        // single shot ultrasonic sensors are quiet between measurements
+        if self.valid || !self.cfg.shows_unplug() || self.is_single_shot() {
+            self.silent_for = Duration::zero();
+        } else {
+            self.silent_for += from_last_hw_read;
+        }
+        if self.silent_for >= SENSOR_LOST_TIMEOUT {
+            self.disconnect();
+            return;
+        }
+
+        let values = if self.valid {
+            Some([self.v1(), self.v2(), self.v3(), self.v4()])
+        } else {
+            None
+        };
+        self.filtered = self.filters.process(values, self.cfg.scalar_channels());
     }
 
-    pub fn soft_calibration(&mut self) {
-        match &self.cfg {
-            SensorConfiguration::Gyro(mode) => match mode {
-                GyroSensorMode::RATE => {
-                    self.data[2] = 0;
-                    self.data[3] = ev3rt::gyro_sensor_get_rate(self.port_idx) as u16;
-                }
-                _ => {}
-            },
-            _ => {}
+    fn read_us_shot(&mut self) {
+        if let Some(val) = self.us_shot {
+            self.data[0] = val as u16;
+            self.valid = true;
+        }
+    }
+
+    fn is_single_shot(&self) -> bool {
+        self.cfg == SensorConfiguration::Ultrasonic(UsSensorMode::SINGLE)
+    }
+
+    // Asks an ultrasonic sensor in SINGLE mode for one measurement. The
+    // sensor pings from the next read() on and the result is taken once it
+    // has settled (SENSOR_MODE_SETTLE_TIME), then it goes quiet again. Until
+    // then there is no reading; afterwards the result stays until the next
+    // trigger.
+    pub fn trigger_measurement(&mut self) -> bool {
+        if !self.is_single_shot() || !self.cfg_applied {
+            return false;
+        }
+        self.us_trigger = Some(Duration::zero());
+        self.us_shot = None;
+        self.valid = false;
+        true
+    }
+
+    pub fn hard_calibration(&mut self) {
+        if let SensorConfiguration::Gyro(_) = &self.cfg {
+            self.hw.gyro_sensor_reset(self.port_idx);
+            self.gyro.set_heading(0);
+        }
+    }
+
+    pub fn soft_calibration(&mut self) {
+        if let SensorConfiguration::Gyro(GyroSensorMode::RATE) = &self.cfg {
+            let mut sum = 0;
+            for _ in 0..GYRO_CALIBRATION_SAMPLES {
+                sum += self.hw.gyro_sensor_get_rate(self.port_idx) as i32;
+                self.hw.msleep(2);
+            }
+            self.gyro.set_bias(sum * 1000 / GYRO_CALIBRATION_SAMPLES);
+            self.gyro.set_heading(0);
         }
     }
 }
 
-pub struct MotorData {
+pub struct MotorData<B = Ev3rt> {
+    hw: B,
     port_idx: MotorPort,
     cfg: MotorType,
     cfg_applied: bool,
     pwr: i8,
     pos: i32,
     pos_target: i32,
-    pos_i: i32,
+    pos_i: i64,
+    pos_last_diff: Option<i32>,
+    pos_cfg: PositionConfig,
+    pos_zones: PositionZones,
+    regulator: SpeedRegulator,
+    velocity: SpeedEstimator,
+    profile: Option<MotionProfile>,
+    move_done: bool,
+    move_done_event: bool,
+    stall: StallDetector,
+    // backend clock at the previous read()
+    last_read: Option<u64>,
 }
 
+// A profiled move is done when the profile is over and the motor is this
+// close (deg) to the target
+pub const MOVE_TOLERANCE: i32 = 2;
+
+// Default window of MotorData::speed: 50ms keep the encoder steps within
+// 20 deg/s
+pub const MOTOR_SPEED_WINDOW: Duration = Duration::from_msec(50);
+
+#[cfg(target_arch = "arm")]
 impl MotorData {
+    pub fn new(port: MotorPort) -> MotorData {
+        Self::with_backend(port, Ev3rt)
+    }
+}
+
+impl<B: Backend> MotorData<B> {
     fn clear_data(&mut self) {
         self.pwr = 0;
         self.pos = 0;
+        self.velocity.reset();
     }
 
-    pub fn new(port: MotorPort) -> MotorData {
+    pub fn with_backend(port: MotorPort, hw: B) -> MotorData<B> {
         MotorData {
+            hw,
             port_idx: port,
             cfg: MotorType::NONE,
             cfg_applied: false,
@@ -402,6 +1423,16 @@ impl MotorData {
             pos: 0,
             pos_target: 0,
             pos_i: 0,
+            pos_last_diff: None,
+            pos_cfg: PositionConfig::new(),
+            pos_zones: PositionZones::new(),
+            regulator: SpeedRegulator::new(),
+            velocity: SpeedEstimator::new(MOTOR_SPEED_WINDOW),
+            profile: None,
+            move_done: false,
+            move_done_event: false,
+            stall: StallDetector::new(),
+            last_read: None,
         }
     }
 
@@ -413,6 +1444,11 @@ impl MotorData {
         self.pwr.into()
     }
 
+    // Unpowered and not turning
+    pub fn is_idle(&self) -> bool {
+        (self.pwr == 0 || self.stall.power_cut()) && self.speed() == 0
+    }
+
     pub fn set_power(&mut self, power: i32) {
         self.pwr = if power > 100 {
             100
@@ -424,86 +1460,198 @@ impl MotorData {
     }
 
     pub fn apply_power(&self) {
-        motor_set_power(self.port(), self.pwr.into());
+        let pwr = if self.stall.power_cut() {
+            0
+        } else {
+            self.pwr.into()
+        };
+        self.hw.motor_set_power(self.port(), pwr);
     }
 
-    pub fn set_power_to_position_fixed(&mut self, target: i32) {
-        const OUTER_ZONE_POWER: i32 = 60;
-        const OUTER_ZONE: i32 = 12;
-        const MIDDLE_ZONE_FACTOR: i32 = 2;
-        const INNER_ZONE_FACTOR: i32 = 1;
-        const INNER_ZONE: i32 = 8;
-
-        //const OUTER_ZONE_POWER: i32 = 80;
-        //const OUTER_ZONE: i32 = 12;
-        //const MIDDLE_ZONE_FACTOR: i32 = 3;
-        //const INNER_ZONE_FACTOR: i32 = 2;
-        //const INNER_ZONE: i32 = 8;
-
-        let diff = target - self.pos;
-        let pwr = if diff > OUTER_ZONE {
-            OUTER_ZONE_POWER
-        } else if diff > INNER_ZONE {
-            diff * MIDDLE_ZONE_FACTOR
-        } else if diff < -OUTER_ZONE {
-            -OUTER_ZONE_POWER
-        } else if diff < -INNER_ZONE {
-            -diff * MIDDLE_ZONE_FACTOR
-        } else {
-            diff * INNER_ZONE_FACTOR
+    pub fn position_config(&self) -> PositionConfig {
+        self.pos_cfg
+    }
+
+    // The limits are taken as magnitudes, whatever their sign
+    pub fn set_position_config(&mut self, cfg: PositionConfig) {
+        self.pos_cfg = PositionConfig {
+            max_error: cfg.max_error.saturating_abs(),
+            max_integral: cfg.max_integral.saturating_abs(),
+            max_power: cfg.max_power.saturating_abs(),
+            ..cfg
         };
-        self.pwr = pwr as i8;
+        self.pos_i = 0;
+        self.pos_last_diff = None;
     }
 
-    pub fn set_power_to_position(&mut self, target: i32, dt: Duration) {
-        const KP_N: i32 = 2;
-        const KP_D: i32 = 1;
-        const KI_N: i32 = 1;
-        const KI_D: i32 = 40000;
-        const OUTER_ZONE: i32 = 10;
-        const OUTER_ZONE_KP: i32 = 4;
-        const OUTER_ZONE_MIN_PWR: i32 = (OUTER_ZONE * KP_N) / KP_D;
-        const DIFF_MAX: i32 = 80;
-        const POS_I_MAX: i32 = DIFF_MAX * 5000;
-        let dt = dt.usec();
+    pub fn position_zones(&self) -> PositionZones {
+        self.pos_zones
+    }
 
+    pub fn set_position_zones(&mut self, zones: PositionZones) {
+        self.pos_zones = zones;
+    }
+
+    pub fn set_power_to_position_fixed(&mut self, target: i32) {
+        let pwr = self.pos_zones.power(target - self.pos);
+        self.set_power(pwr);
+    }
+
+    pub fn set_power_to_position(&mut self, target: i32, dt: Duration) {
         if self.pos_target != target {
             self.pos_target = target;
             self.pos_i = 0;
+            self.pos_last_diff = None;
+        }
+        self.track_position(target, 0, dt);
+    }
+
+    // Position control towards target, plus feed forward power
+    fn track_position(&mut self, target: i32, feed_forward: i64, dt: Duration) {
+        let cfg = self.pos_cfg;
+        let dt = dt.usec() as i64;
+
+        let mut diff = (target - self.pos).clamp(-cfg.max_error, cfg.max_error);
+        if diff.abs() <= cfg.deadband {
+            diff = 0;
         }
 
-        let diff = target - self.pos;
-        let diff = if diff > DIFF_MAX {
-            DIFF_MAX
-        } else if diff < -DIFF_MAX {
-            -DIFF_MAX
-        } else {
-            diff
+        let pwr = cfg.proportional(diff) as i64 + feed_forward;
+
+        let max_i = cfg.max_integral as i64 * 1000;
+        self.pos_i = (self.pos_i + diff as i64 * dt).clamp(-max_i, max_i);
+        let pwr = pwr + self.pos_i * cfg.ki as i64 / 1_000_000_000;
+
+        let pwr = match self.pos_last_diff {
+            Some(last) if dt > 0 => pwr + (diff - last) as i64 * cfg.kd as i64 * 1000 / dt,
+            _ => pwr,
         };
+        self.pos_last_diff = Some(diff);
 
-        let pwr = if diff >= OUTER_ZONE {
-            OUTER_ZONE_MIN_PWR + ((diff - OUTER_ZONE) * OUTER_ZONE_KP)
-        } else if diff <= -OUTER_ZONE {
-            -OUTER_ZONE_MIN_PWR + ((diff + OUTER_ZONE) * OUTER_ZONE_KP)
-        } else {
-            (diff * KP_N) / KP_D
+        let max = cfg.max_power as i64;
+        self.set_power(pwr.clamp(-max, max) as i32);
+    }
+
+    // Starts a move from the current position to target along a motion
+    // profile (speeds in deg/s, acceleration in deg/s^2); update_move() has
+    // to be called at every loop from then on.
+    pub fn start_move(
+        &mut self,
+        target: i32,
+        max_speed: i32,
+        acceleration: i32,
+        shape: ProfileShape,
+    ) {
+        self.profile = Some(MotionProfile::new(
+            self.pos,
+            target,
+            max_speed,
+            acceleration,
+            shape,
+        ));
+        self.pos_target = target;
+        self.pos_i = 0;
+        self.pos_last_diff = None;
+        self.move_done = false;
+        self.move_done_event = false;
+    }
+
+    // Follows the profile of start_move(), and then holds the target
+    pub fn update_move(&mut self, dt: Duration) {
+        self.move_done_event = false;
+        let mut profile = match self.profile {
+            Some(profile) => profile,
+            None => return,
         };
+        profile.advance(dt);
+        self.profile = Some(profile);
+
+        let feed_forward = profile.speed() as i64 * self.regulator.config().kf as i64 / 1000;
+        self.track_position(profile.position(), feed_forward, dt);
 
-        self.pos_i += diff * dt;
-        if self.pos_i > POS_I_MAX {
-            self.pos_i = POS_I_MAX
-        } else if self.pos_i < -POS_I_MAX {
-            self.pos_i = -POS_I_MAX
+        if !self.move_done
+            && profile.is_finished()
+            && (profile.target() - self.pos).abs() <= MOVE_TOLERANCE
+        {
+            self.move_done = true;
+            self.move_done_event = true;
         }
+    }
+
+    pub fn cancel_move(&mut self) {
+        self.profile = None;
+        self.move_done = false;
+        self.move_done_event = false;
+    }
 
-        let pwr = pwr + (self.pos_i * KI_N) / KI_D;
+    pub fn motion_profile(&self) -> Option<&MotionProfile> {
+        self.profile.as_ref()
+    }
+
+    // 0-1000 of the distance, as planned by the profile
+    pub fn move_progress(&self) -> Option<i32> {
+        self.profile.map(|p| p.progress())
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.profile.is_some() && !self.move_done
+    }
+
+    pub fn is_move_done(&self) -> bool {
+        self.move_done
+    }
+
+    // Set by the update_move() that completed the move
+    pub fn move_done_event(&self) -> bool {
+        self.move_done_event
+    }
+
+    // Regulates the power to keep speed (deg/s), call it at every loop.
+    // Other ways of setting the power should be preceded by stop(), so that
+    // a later set_speed starts from standstill again.
+    pub fn set_speed(&mut self, speed: i32, dt: Duration) {
+        let pwr = self.regulator.update(speed, self.speed(), dt);
         self.set_power(pwr);
     }
 
+    pub fn speed_config(&self) -> SpeedConfig {
+        self.regulator.config()
+    }
+
+    pub fn set_speed_config(&mut self, cfg: SpeedConfig) {
+        self.regulator.set_config(cfg);
+    }
+
+    // Target, acceleration limited setpoint and measured speed of set_speed
+    pub fn speed_regulator(&self) -> &SpeedRegulator {
+        &self.regulator
+    }
+
+    pub fn target_speed(&self) -> i32 {
+        self.regulator.target()
+    }
+
+    pub fn actual_speed(&self) -> i32 {
+        self.speed()
+    }
+
     pub fn position(&self) -> i32 {
         self.pos
     }
 
+    // Measured by read() over the speed filter window, deg/s
+    pub fn speed(&self) -> i32 {
+        self.velocity.speed()
+    }
+
+    pub fn speed_filter_window(&self) -> Duration {
+        self.velocity.window()
+    }
+
+    pub fn set_speed_filter_window(&mut self, window: Duration) {
+        self.velocity.set_window(window);
+    }
+
     pub fn configuration_applied(&self) -> bool {
         self.cfg_applied
     }
@@ -511,53 +1659,103 @@ impl MotorData {
     pub fn configure(&mut self, cfg: MotorType) {
         self.cfg = cfg;
         self.cfg_applied = false;
+        self.regulator.reset();
+        self.cancel_move();
+        self.stall.reset();
         self.clear_data();
     }
 
-    pub fn attempt_cfg_apply(&mut self) {
+    pub fn attempt_cfg_apply(&mut self) -> Result<(), ConfigError> {
         self.clear_data();
-        let er = motor_config(self.port(), self.cfg);
+        let er = self.hw.motor_config(self.port(), self.cfg);
         if er == ER::OK {
             self.cfg_applied = true;
-            motor_stop(self.port(), false);
+            self.hw.motor_stop(self.port(), false);
             match self.cfg {
                 MotorType::LARGE | MotorType::MEDIUM => {
-                    motor_reset_counts(self.port());
+                    self.hw.motor_reset_counts(self.port());
                 }
                 _ => {}
             }
+            Ok(())
+        } else {
+            Err(ConfigError::Motor {
+                port: self.port(),
+                cfg: self.cfg,
+                reason: ConfigErrorReason::Driver(er),
+            })
         }
     }
 
     pub fn stop(&mut self, brake: bool) {
         self.pwr = 0;
-        motor_stop(self.port(), brake);
+        self.regulator.reset();
+        self.cancel_move();
+        self.stall.reset();
+        self.hw.motor_stop(self.port(), brake);
     }
 
+    // Times itself with the backend clock, so that it can be called at any
+    // rate (Ev3::read calls it at every loop)
     pub fn read(&mut self) {
+        let now = self.hw.get_utime();
+        let from_last_read = match self.last_read {
+            Some(last) => Duration::from_usec((now - last) as i32),
+            None => Duration::zero(),
+        };
+        self.last_read = Some(now);
         match self.cfg {
-            MotorType::LARGE | MotorType::MEDIUM => self.pos = motor_get_counts(self.port()),
+            MotorType::LARGE | MotorType::MEDIUM => {
+                self.pos = self.hw.motor_get_counts(self.port());
+                self.velocity.update(self.pos, from_last_read);
+                self.stall
+                    .update(self.pwr.into(), self.velocity.speed(), from_last_read);
+            }
             _ => {}
         }
     }
 
+    pub fn stall_config(&self) -> StallConfig {
+        self.stall.config()
+    }
+
+    pub fn set_stall_config(&mut self, cfg: StallConfig) {
+        self.stall.set_config(cfg);
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.stall.is_stalled()
+    }
+
+    // Set by the read() that found the motor stalled
+    pub fn stall_event(&self) -> bool {
+        self.stall.stall_event()
+    }
+
+    // Powers the motor again after a stall cut its power
+    pub fn reset_stall(&mut self) {
+        self.stall.reset();
+    }
+
     pub fn reset_position(&mut self) {
-        motor_reset_counts(self.port());
+        self.hw.motor_reset_counts(self.port());
         self.pos = 0;
+        self.regulator.reset();
+        self.velocity.reset();
     }
 }
 
 #[derive(Clone, Copy, PartialEq)]
 struct Point {
-    x: u8,
-    y: u8,
+    x: i16,
+    y: i16,
 }
 
 impl Point {
     fn new(x: i32, y: i32) -> Point {
         Point {
-            x: x as u8,
-            y: y as u8,
+            x: x as i16,
+            y: y as i16,
         }
     }
 
@@ -570,8 +1768,8 @@ impl Point {
 
     fn mov(self: Point, x: i32, y: i32) -> Point {
         Point {
-            x: ((self.x as i32) + x) as u8,
-            y: ((self.y as i32) + y) as u8,
+            x: ((self.x as i32) + x) as i16,
+            y: ((self.y as i32) + y) as i16,
         }
     }
 }
@@ -703,8 +1901,8 @@ impl Glyph {
                         && y <= (MAX_GLYPH_POINTS as i32)
                     {
                         points[step] = Point {
-                            x: x as u8,
-                            y: y as u8,
+                            x: x as i16,
+                            y: y as i16,
                         };
                     } else {
                         points[step] = POINT_LAST;
@@ -723,7 +1921,7 @@ impl Glyph {
         Glyph { id, wide, points }
     }
 
-    fn draw(&self, s: &Screen, x: i32, y: i32, bold: bool) {
+    fn draw<B: Backend>(&self, s: &Screen<B>, x: i32, y: i32, bold: bool) {
         let origin = Point::new(x, y);
 
         s.clear_in_info(
@@ -895,7 +2093,8 @@ pub enum ScreenOrientation {
 }
 
 const MAX_INFOS: usize = 10;
-pub struct Screen {
+pub struct Screen<B = Ev3rt> {
+    hw: B,
     or: ScreenOrientation,
 
     must_refresh: bool,
@@ -919,9 +2118,17 @@ pub struct Screen {
     glyphs: [Glyph; Gph::LAST as usize],
 }
 
+#[cfg(target_arch = "arm")]
 impl Screen {
     pub fn new() -> Screen {
+        Self::with_backend(Ev3rt)
+    }
+}
+
+impl<B: Backend> Screen<B> {
+    pub fn with_backend(hw: B) -> Screen<B> {
         let s = Screen {
+            hw,
             or: ScreenOrientation::Up,
             must_refresh: true,
 
@@ -1039,9 +2246,9 @@ impl Screen {
         };
         for i in 0..(Gph::LAST as usize) {
             if s.glyphs[i].id as usize != i {
-                ev3rt::lcd_clear();
-                ev3rt::lcd_draw_string("BAD GLYPH IDX", 20, 20);
-                ev3rt::abort();
+                s.hw.lcd_fill_rect(0, 0, LCD_WIDTH, LCD_HEIGHT, LcdColor::WHITE);
+                s.hw.lcd_draw_string("BAD GLYPH IDX", 20, 20);
+                s.hw.abort();
             }
         }
         s
@@ -1056,12 +2263,12 @@ impl Screen {
         self.must_refresh = true;
 
         self.w = match or {
-            ScreenOrientation::Up | ScreenOrientation::Down => ev3rt::LCD_WIDTH,
-            ScreenOrientation::Left | ScreenOrientation::Right => ev3rt::LCD_HEIGHT,
+            ScreenOrientation::Up | ScreenOrientation::Down => LCD_WIDTH,
+            ScreenOrientation::Left | ScreenOrientation::Right => LCD_HEIGHT,
         };
         self.h = match or {
-            ScreenOrientation::Up | ScreenOrientation::Down => ev3rt::LCD_HEIGHT,
-            ScreenOrientation::Left | ScreenOrientation::Right => ev3rt::LCD_WIDTH,
+            ScreenOrientation::Up | ScreenOrientation::Down => LCD_HEIGHT,
+            ScreenOrientation::Left | ScreenOrientation::Right => LCD_WIDTH,
         };
 
         self.info_x = 0;
@@ -1283,9 +2490,9 @@ impl Screen {
     fn screen_to_absolute(&self, x: i32, y: i32) -> (i32, i32) {
         match self.or {
             ScreenOrientation::Up => (x, y),
-            ScreenOrientation::Down => (ev3rt::LCD_WIDTH - x, ev3rt::LCD_HEIGHT - y),
-            ScreenOrientation::Left => (y, ev3rt::LCD_HEIGHT - x),
-            ScreenOrientation::Right => (ev3rt::LCD_WIDTH - y, x),
+            ScreenOrientation::Down => (LCD_WIDTH - x, LCD_HEIGHT - y),
+            ScreenOrientation::Left => (y, LCD_HEIGHT - x),
+            ScreenOrientation::Right => (LCD_WIDTH - y, x),
         }
     }
 
@@ -1309,8 +2516,8 @@ impl Screen {
     fn info_to_absolute_point(&self, p: Point) -> Point {
         let (x, y) = self.info_to_absolute_pos(p.x as i32, p.y as i32);
         Point {
-            x: x as u8,
-            y: y as u8,
+            x: x as i16,
+            y: y as i16,
         }
     }
 
@@ -1322,12 +2529,12 @@ impl Screen {
         let p1 = self.in_info(p1);
         let p2 = self.in_info(p2);
         let (x0, y0, x1, y1) = (p1.x as i32, p1.y as i32, p2.x as i32, p2.y as i32);
-        ev3rt::lcd_draw_line(x0, y0, x1, y1);
+        self.hw.lcd_draw_line(x0, y0, x1, y1);
         if bold {
-            ev3rt::lcd_draw_line(x0 + 1, y0 + 1, x1 + 1, y1 + 1);
-            ev3rt::lcd_draw_line(x0 + 1, y0 - 1, x1 + 1, y1 - 1);
-            ev3rt::lcd_draw_line(x0 - 1, y0 + 1, x1 - 1, y1 + 1);
-            ev3rt::lcd_draw_line(x0 - 1, y0 - 1, x1 - 1, y1 - 1);
+            self.hw.lcd_draw_line(x0 + 1, y0 + 1, x1 + 1, y1 + 1);
+            self.hw.lcd_draw_line(x0 + 1, y0 - 1, x1 + 1, y1 - 1);
+            self.hw.lcd_draw_line(x0 - 1, y0 + 1, x1 - 1, y1 + 1);
+            self.hw.lcd_draw_line(x0 - 1, y0 - 1, x1 - 1, y1 - 1);
         }
     }
 
@@ -1343,7 +2550,7 @@ impl Screen {
             ScreenOrientation::Left => (x, y - h),
             ScreenOrientation::Right => (x - w, y),
         };
-        ev3rt::lcd_fill_rect(x, y, w, h, color);
+        self.hw.lcd_fill_rect(x, y, w, h, color);
     }
 
     fn clear_in_info(&self, p: Point, w: i32, h: i32) {
@@ -1353,7 +2560,7 @@ impl Screen {
     fn draw_graph_line_from_coords(&self, x0: i32, y0: i32, x1: i32, y1: i32) {
         let (x0a, y0a) = self.graph_to_absolute_pos(x0, y0);
         let (x1a, y1a) = self.graph_to_absolute_pos(x1, y1);
-        ev3rt::lcd_draw_line(x0a, y0a, x1a, y1a);
+        self.hw.lcd_draw_line(x0a, y0a, x1a, y1a);
     }
 
     pub fn draw_graph_line(&self, x0: i32, y0: i32, x1: i32, y1: i32, bold: bool) {
@@ -1373,8 +2580,8 @@ impl Screen {
 
     fn move_point(from: Point, length: i32, angle: i32) -> Point {
         let mut to = from;
-        to.x += (cos(angle) * length / 1000) as u8;
-        to.y += (sin(angle) * length / 1000) as u8;
+        to.x += (cos(angle) * length / 1000) as i16;
+        to.y += (sin(angle) * length / 1000) as i16;
 
         to
     }
@@ -1400,11 +2607,11 @@ impl Screen {
 
         let center = Point::new(center_x, center_y);
 
-        let mut from = Screen::move_point(center, radius, start_angle);
+        let mut from = Self::move_point(center, radius, start_angle);
 
         for s in 0..steps {
             let to_angle = start_angle + (s * step);
-            let to = Screen::move_point(center, radius, to_angle);
+            let to = Self::move_point(center, radius, to_angle);
             self.draw_graph_line_from_points(from, to, bold);
             from = to;
         }
@@ -1419,16 +2626,25 @@ impl Screen {
     }
 }
 
-pub struct Leds {
+pub struct Leds<B = Ev3rt> {
+    hw: B,
     todo_red: bool,
     todo_green: bool,
     done_red: bool,
     done_green: bool,
 }
 
+#[cfg(target_arch = "arm")]
 impl Leds {
     pub fn new() -> Self {
+        Self::with_backend(Ev3rt)
+    }
+}
+
+impl<B: Backend> Leds<B> {
+    pub fn with_backend(hw: B) -> Self {
         Leds {
+            hw,
             todo_red: false,
             todo_green: false,
             done_red: false,
@@ -1464,7 +2680,7 @@ impl Leds {
     }
 
     pub fn reset(&mut self) {
-        *self = Self::new();
+        *self = Self::with_backend(self.hw);
         self.apply();
     }
 
@@ -1476,7 +2692,7 @@ impl Leds {
                 (true, false) => LedColor::RED,
                 (true, true) => LedColor::ORANGE,
             };
-            ev3rt::led_set_color(c);
+            self.hw.led_set_color(c);
             self.done_red = self.todo_red;
             self.done_green = self.todo_green;
         }
@@ -1489,19 +2705,19 @@ pub struct Duration {
 }
 
 impl Duration {
-    pub fn new(ticks: i32) -> Self {
+    pub const fn new(ticks: i32) -> Self {
         Self { ticks }
     }
 
-    pub fn from_usec(usec: i32) -> Self {
+    pub const fn from_usec(usec: i32) -> Self {
         Self::new(usec)
     }
 
-    pub fn from_msec(msec: i32) -> Self {
+    pub const fn from_msec(msec: i32) -> Self {
         Self::new(msec * 1_000)
     }
 
-    pub fn zero() -> Self {
+    pub const fn zero() -> Self {
         Self { ticks: 0 }
     }
 
@@ -1544,16 +2760,25 @@ impl core::ops::SubAssign for Duration {
     }
 }
 
-pub struct Time {
+pub struct Time<B = Ev3rt> {
+    hw: B,
     ticks: u64,
     duration_from_last_read: Duration,
     duration_from_last_reset: Duration,
 }
 
+#[cfg(target_arch = "arm")]
 impl Time {
     pub fn new() -> Self {
+        Self::with_backend(Ev3rt)
+    }
+}
+
+impl<B: Backend> Time<B> {
+    pub fn with_backend(hw: B) -> Self {
         Self {
-            ticks: ev3rt::get_utime(),
+            hw,
+            ticks: hw.get_utime(),
             duration_from_last_read: Duration::zero(),
             duration_from_last_reset: Duration::zero(),
         }
@@ -1561,7 +2786,7 @@ impl Time {
 
     fn compute_duration_from_last_read(&self) -> (Duration, u64) {
         let previous = self.ticks;
-        let current = ev3rt::get_utime();
+        let current = self.hw.get_utime();
         let delta = Duration::new((current - previous) as i32);
         (delta, current)
     }
@@ -1570,7 +2795,7 @@ impl Time {
         let (mut delta, mut current) = self.compute_duration_from_last_read();
         // It turns out that waiting "a bit" gives more reliable sensor reads...
         while delta.usec() < 800 {
-            ev3rt::msleep(1);
+            self.hw.msleep(1);
             let (d, c) = self.compute_duration_from_last_read();
             delta = d;
             current = c;
@@ -1656,7 +2881,8 @@ impl KeyStatus {
     }
 }
 
-pub struct Keys {
+pub struct Keys<B = Ev3rt> {
+    hw: B,
     pub up: KeyStatus,
     pub down: KeyStatus,
     pub left: KeyStatus,
@@ -1665,9 +2891,17 @@ pub struct Keys {
     pub back: KeyStatus,
 }
 
+#[cfg(target_arch = "arm")]
 impl Keys {
     pub fn new() -> Self {
+        Self::with_backend(Ev3rt)
+    }
+}
+
+impl<B: Backend> Keys<B> {
+    pub fn with_backend(hw: B) -> Self {
         Self {
+            hw,
             up: KeyStatus::new(),
             down: KeyStatus::new(),
             left: KeyStatus::new(),
@@ -1679,12 +2913,12 @@ impl Keys {
 
     pub fn read(&mut self, delta: Duration, or: ScreenOrientation) {
         let (up, down, left, right, enter, back) = (
-            button_is_pressed(Button::UP),
-            button_is_pressed(Button::DOWN),
-            button_is_pressed(Button::LEFT),
-            button_is_pressed(Button::RIGHT),
-            button_is_pressed(Button::ENTER),
-            button_is_pressed(Button::BACK),
+            self.hw.button_is_pressed(Button::UP),
+            self.hw.button_is_pressed(Button::DOWN),
+            self.hw.button_is_pressed(Button::LEFT),
+            self.hw.button_is_pressed(Button::RIGHT),
+            self.hw.button_is_pressed(Button::ENTER),
+            self.hw.button_is_pressed(Button::BACK),
         );
 
         let (up, right, down, left) = match or {
@@ -1703,130 +2937,132 @@ impl Keys {
     }
 }
 
-pub struct Ev3 {
-    minimum_sensor_read_period: Duration,
-    elapsed_sensor_read_period: Duration,
-    pub sensors: [SensorData; 4],
-    pub motors: [MotorData; 4],
-    pub screen: Screen,
-    pub leds: Leds,
-    pub time: Time,
-    pub keys: Keys,
+pub struct Ev3<B = Ev3rt> {
+    hw: B,
+    pub sensors: [SensorData<B>; 4],
+    pub motors: [MotorData<B>; 4],
+    pub screen: Screen<B>,
+    pub leds: Leds<B>,
+    pub time: Time<B>,
+    pub keys: Keys<B>,
 }
 
-pub trait SensorGetterMut {
-    fn sensor(&mut self, port: SensorPort) -> &mut SensorData;
+pub trait SensorGetterMut<B = Ev3rt> {
+    fn sensor(&mut self, port: SensorPort) -> &mut SensorData<B>;
 }
-pub trait SensorGetter {
-    fn sensor(&self, port: SensorPort) -> &SensorData;
+pub trait SensorGetter<B = Ev3rt> {
+    fn sensor(&self, port: SensorPort) -> &SensorData<B>;
 }
-impl SensorGetterMut for Ev3 {
-    fn sensor(&mut self, port: SensorPort) -> &mut SensorData {
+impl<B: Backend> SensorGetterMut<B> for Ev3<B> {
+    fn sensor(&mut self, port: SensorPort) -> &mut SensorData<B> {
         &mut (self.sensors[port as usize])
     }
 }
-impl SensorGetter for Ev3 {
-    fn sensor(&self, port: SensorPort) -> &SensorData {
+impl<B: Backend> SensorGetter<B> for Ev3<B> {
+    fn sensor(&self, port: SensorPort) -> &SensorData<B> {
         &(self.sensors[port as usize])
     }
 }
 
-pub trait MotorGetterMut {
-    fn motor(&mut self, port: MotorPort) -> &mut MotorData;
+pub trait MotorGetterMut<B = Ev3rt> {
+    fn motor(&mut self, port: MotorPort) -> &mut MotorData<B>;
 }
-pub trait MotorGetter {
-    fn motor(&self, port: MotorPort) -> &MotorData;
+pub trait MotorGetter<B = Ev3rt> {
+    fn motor(&self, port: MotorPort) -> &MotorData<B>;
 }
-impl MotorGetterMut for Ev3 {
-    fn motor(&mut self, port: MotorPort) -> &mut MotorData {
+impl<B: Backend> MotorGetterMut<B> for Ev3<B> {
+    fn motor(&mut self, port: MotorPort) -> &mut MotorData<B> {
         &mut (self.motors[port as usize])
     }
 }
-impl MotorGetter for Ev3 {
-    fn motor(&self, port: MotorPort) -> &MotorData {
+impl<B: Backend> MotorGetter<B> for Ev3<B> {
+    fn motor(&self, port: MotorPort) -> &MotorData<B> {
         &(self.motors[port as usize])
     }
 }
 
+#[cfg(target_arch = "arm")]
 impl Ev3 {
     pub fn new() -> Ev3 {
+        Self::with_backend(Ev3rt)
+    }
+}
+
+impl<B: Backend> Ev3<B> {
+    pub fn with_backend(hw: B) -> Ev3<B> {
         Ev3 {
-            minimum_sensor_read_period: Duration::zero(),
-            elapsed_sensor_read_period: Duration::zero(),
+            hw,
             sensors: [
-                SensorData::new(SensorPort::S1),
-                SensorData::new(SensorPort::S2),
-                SensorData::new(SensorPort::S3),
-                SensorData::new(SensorPort::S4),
+                SensorData::with_backend(SensorPort::S1, hw),
+                SensorData::with_backend(SensorPort::S2, hw),
+                SensorData::with_backend(SensorPort::S3, hw),
+                SensorData::with_backend(SensorPort::S4, hw),
             ],
             motors: [
-                MotorData::new(MotorPort::A),
-                MotorData::new(MotorPort::B),
-                MotorData::new(MotorPort::C),
-                MotorData::new(MotorPort::D),
+                MotorData::with_backend(MotorPort::A, hw),
+                MotorData::with_backend(MotorPort::B, hw),
+                MotorData::with_backend(MotorPort::C, hw),
+                MotorData::with_backend(MotorPort::D, hw),
             ],
-            screen: Screen::new(),
-            leds: Leds::new(),
-            time: Time::new(),
-            keys: Keys::new(),
+            screen: Screen::with_backend(hw),
+            leds: Leds::with_backend(hw),
+            time: Time::with_backend(hw),
+            keys: Keys::with_backend(hw),
         }
     }
 
-    pub fn s1(&mut self) -> &mut SensorData {
+    pub fn backend(&self) -> B {
+        self.hw
+    }
+
+    pub fn s1(&mut self) -> &mut SensorData<B> {
         &mut (self.sensors[0])
     }
-    pub fn s2(&mut self) -> &mut SensorData {
+    pub fn s2(&mut self) -> &mut SensorData<B> {
         &mut (self.sensors[1])
     }
-    pub fn s3(&mut self) -> &mut SensorData {
+    pub fn s3(&mut self) -> &mut SensorData<B> {
         &mut (self.sensors[2])
     }
-    pub fn s4(&mut self) -> &mut SensorData {
+    pub fn s4(&mut self) -> &mut SensorData<B> {
         &mut (self.sensors[3])
     }
 
-    pub fn ma(&mut self) -> &mut MotorData {
+    pub fn ma(&mut self) -> &mut MotorData<B> {
         &mut (self.motors[0])
     }
-    pub fn mb(&mut self) -> &mut MotorData {
+    pub fn mb(&mut self) -> &mut MotorData<B> {
         &mut (self.motors[1])
     }
-    pub fn mc(&mut self) -> &mut MotorData {
+    pub fn mc(&mut self) -> &mut MotorData<B> {
         &mut (self.motors[2])
     }
-    pub fn md(&mut self) -> &mut MotorData {
+    pub fn md(&mut self) -> &mut MotorData<B> {
         &mut (self.motors[3])
     }
 
     pub fn lcd_clear(&self) {
-        lcd_fill_rect(0, 0, LCD_WIDTH, LCD_HEIGHT, LcdColor::WHITE);
+        self.hw
+            .lcd_fill_rect(0, 0, LCD_WIDTH, LCD_HEIGHT, LcdColor::WHITE);
     }
 
     pub fn reset(&self) {
-        motor_stop(MotorPort::A, false);
-        motor_stop(MotorPort::B, false);
-        motor_stop(MotorPort::C, false);
-        motor_stop(MotorPort::D, false);
-        sensor_config(SensorPort::S1, SensorType::NONE);
-        sensor_config(SensorPort::S2, SensorType::NONE);
-        sensor_config(SensorPort::S3, SensorType::NONE);
-        sensor_config(SensorPort::S4, SensorType::NONE);
-        led_set_color(LedColor::OFF);
-        lcd_set_font(LcdFont::MEDIUM);
+        self.hw.motor_stop(MotorPort::A, false);
+        self.hw.motor_stop(MotorPort::B, false);
+        self.hw.motor_stop(MotorPort::C, false);
+        self.hw.motor_stop(MotorPort::D, false);
+        self.hw.sensor_config(SensorPort::S1, SensorType::NONE);
+        self.hw.sensor_config(SensorPort::S2, SensorType::NONE);
+        self.hw.sensor_config(SensorPort::S3, SensorType::NONE);
+        self.hw.sensor_config(SensorPort::S4, SensorType::NONE);
+        self.hw.led_set_color(LedColor::OFF);
+        self.hw.lcd_set_font(LcdFont::MEDIUM);
         self.lcd_clear();
-        led_set_color(LedColor::OFF);
+        self.hw.led_set_color(LedColor::OFF);
     }
 
-    pub fn apply_configuration(&mut self) {
-        if self.sensors.iter().any(|s| {
-            if let SensorConfiguration::NxtUltrasonic(_) = s.cfg {
-                true
-            } else {
-                false
-            }
-        }) {
-            self.minimum_sensor_read_period = Duration::from_usec(23567);
-        }
+    pub fn apply_configuration(&mut self) -> ConfigReport {
+        let mut report = ConfigReport::new();
         self.leds.reset();
         self.lcd_clear();
 
@@ -1851,7 +3087,7 @@ impl Ev3 {
         self.screen.setup_info_glyphs(6, &[Gph::M, Gph::B]);
         self.screen.setup_info_glyphs(7, &[Gph::M, Gph::C]);
         self.screen.setup_info_glyphs(8, &[Gph::M, Gph::D]);
-        for s in 0..3usize {
+        for s in 0..4usize {
             if self.sensors[s].cfg == SensorConfiguration::None {
                 self.screen
                     .setup_info_glyphs(1 + s, &[Gph::Minus, Gph::Minus]);
@@ -1864,10 +3100,9 @@ impl Ev3 {
 
         self.screen.set_info_bold(0, true);
         self.screen.render_info();
-        self.ma().attempt_cfg_apply();
-        self.mb().attempt_cfg_apply();
-        self.mc().attempt_cfg_apply();
-        self.md().attempt_cfg_apply();
+        for m in 0..4usize {
+            report.motors[m] = self.motors[m].attempt_cfg_apply().err();
+        }
         self.screen.set_info_bold(0, false);
         self.screen.render_info();
 
@@ -1878,10 +3113,11 @@ impl Ev3 {
 
         self.time.reset();
         while self.time.duration_from_last_reset.msec() < TEST_LENGTH * 4
-            || (!self.s1().cfg_applied)
-            || (!self.s2().cfg_applied)
-            || (!self.s3().cfg_applied)
-            || (!self.s4().cfg_applied)
+            || (self.time.duration_from_last_reset < SENSOR_CONFIG_TIMEOUT
+                && ((!self.s1().cfg_applied)
+                    || (!self.s2().cfg_applied)
+                    || (!self.s3().cfg_applied)
+                    || (!self.s4().cfg_applied)))
         {
             self.screen.deselect_infos();
             let millis = self.time.duration_from_last_reset.msec();
@@ -1889,7 +3125,8 @@ impl Ev3 {
             let current_motor = (millis / TEST_LENGTH) as usize;
             if current_motor < 4 {
                 if current_motor > 0 {
-                    motor_set_power(self.motors[current_motor - 1].port(), 0);
+                    self.hw
+                        .motor_set_power(self.motors[current_motor - 1].port(), 0);
                 }
                 self.screen.set_info_bold(5 + current_motor, true);
                 let mut power = 0;
@@ -1907,7 +3144,8 @@ impl Ev3 {
                         0
                     }
                 }
-                motor_set_power(self.motors[current_motor].port(), power);
+                self.hw
+                    .motor_set_power(self.motors[current_motor].port(), power);
             } else {
                 self.stop();
             }
@@ -1916,7 +3154,8 @@ impl Ev3 {
                 let todo = !self.sensors[current_sensor].configuration_applied();
                 self.screen.set_info_bold(1 + current_sensor, todo);
                 if todo {
-                    self.sensors[current_sensor].attempt_cfg_apply();
+                    report.sensors[current_sensor] =
+                        self.sensors[current_sensor].attempt_cfg_apply().err();
                 }
             }
 
@@ -1926,6 +3165,7 @@ impl Ev3 {
         self.screen.set_info_count(0);
         self.screen.render_info();
         self.lcd_clear();
+        report
     }
 
     pub fn stop(&mut self) {
@@ -1935,22 +3175,21 @@ impl Ev3 {
         self.md().stop(false);
     }
 
+    // Every sensor is read at its own period (see SensorData::read_period).
+    // Also notices unplugged sensors and reapplies their configuration once
+    // they are back (see SensorData::is_lost)
     pub fn read(&mut self) {
         self.time.read();
         let elapsed = self.time.from_last_read();
-        self.elapsed_sensor_read_period += elapsed;
-        if self.elapsed_sensor_read_period < self.minimum_sensor_read_period {
-            self.s1().skip_read(elapsed);
-            self.s2().skip_read(elapsed);
-            self.s3().skip_read(elapsed);
-            self.s4().skip_read(elapsed);
-        } else {
-            self.elapsed_sensor_read_period = Duration::zero();
-            self.s1().read(elapsed);
-            self.s2().read(elapsed);
-            self.s3().read(elapsed);
-            self.s4().read(elapsed);
+        // gyros track their bias only while the robot is not driving
+        let stationary = self.motors.iter().all(|m| m.is_idle());
+        for s in self.sensors.iter_mut() {
+            s.gyro_mut().set_stationary(stationary);
         }
+        self.s1().read(elapsed);
+        self.s2().read(elapsed);
+        self.s3().read(elapsed);
+        self.s4().read(elapsed);
         self.ma().read();
         self.mb().read();
         self.mc().read();
@@ -1958,13 +3197,45 @@ impl Ev3 {
         self.keys.read(elapsed, self.screen.orientation())
     }
 
+    // Finds out what is plugged in every sensor port.
+    // Ports left at SensorConfiguration::None get the default configuration
+    // for the detected sensor (see SensorData::configuration), the others are
+    // checked against it: a different sensor is reported as
+    // ConfigErrorReason::WrongDevice. Ports expecting a sensor that cannot be
+    // detected only fail if another sensor answers there.
+    pub fn detect_sensors(&mut self) -> ConfigReport {
+        let mut report = ConfigReport::new();
+        for s in self.sensors.iter_mut() {
+            let found = s.detect();
+            let expected = s.cfg;
+            let idx = s.port() as usize;
+            let mismatch = if expected.is_detectable() {
+                expected.sensor_type() != found
+            } else {
+                found != SensorType::NONE
+            };
+            if expected == SensorConfiguration::None {
+                s.configure(SensorConfiguration::default_for(found));
+            } else if mismatch {
+                report.sensors[idx] = Some(ConfigError::Sensor {
+                    port: s.port(),
+                    cfg: expected,
+                    reason: ConfigErrorReason::WrongDevice(found),
+                });
+                continue;
+            }
+            report.sensors[idx] = s.attempt_cfg_apply().err();
+        }
+        report
+    }
+
     pub fn calibration(&mut self) {
-        ev3rt::msleep(200);
+        self.hw.msleep(200);
         self.s1().hard_calibration();
         self.s2().hard_calibration();
         self.s3().hard_calibration();
         self.s4().hard_calibration();
-        ev3rt::msleep(100);
+        self.hw.msleep(100);
         self.s1().soft_calibration();
         self.s2().soft_calibration();
         self.s3().soft_calibration();
@@ -1997,12 +3268,27 @@ pub fn cos(v: i32) -> i32 {
     sin(v + 90)
 }
 
+pub(crate) fn isqrt(v: i64) -> i64 {
+    if v <= 0 {
+        return 0;
+    }
+    let mut x = v;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + v / x) / 2;
+    }
+    x
+}
+
+#[cfg(target_arch = "arm")]
 impl Default for Ev3 {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(target_arch = "arm")]
 impl Default for Keys {
     fn default() -> Self {
         Self::new()
@@ -2015,14 +3301,1149 @@ impl Default for KeyStatus {
     }
 }
 
+#[cfg(target_arch = "arm")]
 impl Default for Time {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(target_arch = "arm")]
 impl Default for Screen {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(target_arch = "arm")]
+impl Default for Leds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockBackend, MockSensor};
+
+    const LOOP: Duration = Duration::from_msec(1);
+
+    // A sensor in S1, plugged, set up and configured
+    fn plugged(
+        hw: &MockBackend,
+        st: SensorType,
+        cfg: SensorConfiguration,
+        setup: impl FnOnce(&mut MockSensor),
+    ) -> SensorData<&MockBackend> {
+        hw.plug_sensor(SensorPort::S1, st);
+        hw.update_sensor(SensorPort::S1, setup);
+        let mut sensor = SensorData::with_backend(SensorPort::S1, hw);
+        sensor.configure(cfg);
+        assert!(sensor.attempt_cfg_apply().is_ok());
+        sensor
+    }
+
+    #[test]
+    fn configure_read_apply_loop() {
+        let hw = MockBackend::new();
+        hw.plug_sensor(SensorPort::S1, SensorType::ULTRASONIC);
+        hw.update_sensor(SensorPort::S1, |s| s.distance = 50);
+        hw.plug_sensor(SensorPort::S2, SensorType::COLOR);
+        hw.update_sensor(SensorPort::S2, |s| s.reflect = 40);
+        hw.plug_motor(MotorPort::A, MotorType::LARGE);
+
+        let mut ev3 = Ev3::with_backend(&hw);
+        ev3.s1()
+            .configure(SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE));
+        ev3.s2()
+            .configure(SensorConfiguration::Color(ColorSensorMode::REFLECT));
+        // nothing in S3: reported, and the configuration gives up on it
+        ev3.s3()
+            .configure(SensorConfiguration::Color(ColorSensorMode::REFLECT));
+        ev3.ma().configure(MotorType::LARGE);
+
+        let report = ev3.apply_configuration();
+        assert!(report.sensor(SensorPort::S1).is_none());
+        assert!(report.sensor(SensorPort::S2).is_none());
+        assert!(report.sensor(SensorPort::S3).is_some());
+        assert!(report.motor(MotorPort::A).is_none());
+
+        // the motor turns 1 deg per loop at half power
+        let mut moved = 0;
+        for _ in 0..2000 {
+            ev3.read();
+            let dt = ev3.time.from_last_read();
+            ev3.ma().set_power_to_position(90, dt);
+            ev3.apply();
+            moved += hw.motor(MotorPort::A).power;
+            hw.update_motor(MotorPort::A, |m| m.counts += moved / 50);
+            moved %= 50;
+        }
+        assert!((ev3.ma().position() - 90).abs() <= 2);
+        assert!(ev3.s1().reading() == Some(SensorReading::Distance(500)));
+        assert!(ev3.s2().reading() == Some(SensorReading::Reflect(40)));
+
+        hw.update_sensor(SensorPort::S1, |s| s.distance = 20);
+        for _ in 0..100 {
+            ev3.read();
+        }
+        assert!(ev3.s1().reading() == Some(SensorReading::Distance(200)));
+    }
+
+    #[test]
+    fn typed_readings() {
+        let hw = MockBackend::new();
+        let mut us = plugged(
+            &hw,
+            SensorType::ULTRASONIC,
+            SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE),
+            |s| s.distance = 50,
+        );
+        assert!(us.reading().is_none());
+        us.read(LOOP);
+        assert!(us.reading() == Some(SensorReading::Distance(500)));
+        hw.update_sensor(SensorPort::S1, |s| s.distance = -1);
+        us.read(LOOP);
+        assert!(us.reading().is_none());
+        assert_eq!(us.data[0], 0xffff);
+
+        let hw = MockBackend::new();
+        let mut color = plugged(
+            &hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::RGB),
+            |s| {
+                s.rgb = RgbRaw {
+                    r: 10,
+                    g: 20,
+                    b: 30,
+                }
+            },
+        );
+        color.read(LOOP);
+        assert!(
+            color.reading()
+                == Some(SensorReading::Rgb {
+                    r: 10,
+                    g: 20,
+                    b: 30
+                })
+        );
+
+        let hw = MockBackend::new();
+        let mut gyro = plugged(
+            &hw,
+            SensorType::GYRO,
+            SensorConfiguration::Gyro(GyroSensorMode::ANGLE),
+            |s| s.gyro_angle = -90,
+        );
+        gyro.read(LOOP);
+        assert!(gyro.reading() == Some(SensorReading::GyroAngle(-90)));
+        assert_eq!(gyro.val(), -90);
+    }
+
+    #[test]
+    fn color_codes() {
+        let hw = MockBackend::new();
+        let mut sensor = plugged(
+            &hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::COLOR),
+            |s| s.color = SensorColorCode::RED as i32,
+        );
+        sensor.read(LOOP);
+        assert!(sensor.color() == Some(Color::Red));
+        assert_eq!(sensor.color().map(Color::name), Some("RED"));
+        hw.update_sensor(SensorPort::S1, |s| s.color = SensorColorCode::NONE as i32);
+        sensor.read(LOOP);
+        assert!(sensor.color() == Some(Color::Unknown));
+    }
+
+    #[test]
+    fn ir_seek_and_remote() {
+        let hw = MockBackend::new();
+        let mut seek = plugged(
+            &hw,
+            SensorType::INFRARED,
+            SensorConfiguration::Ir(IrSensorMode::SEEK),
+            |s| {
+                s.ir_seek = IrSeek {
+                    heading: [-12, 0, 0, 25],
+                    distance: [30, -128, -128, 100],
+                }
+            },
+        );
+        seek.read(LOOP);
+        assert!(
+            seek.ir_beacon(1)
+                == Some(IrBeacon {
+                    heading: -12,
+                    distance: 30
+                })
+        );
+        assert!(seek.ir_beacon(2).is_none());
+        assert!(
+            seek.ir_beacon(4)
+                == Some(IrBeacon {
+                    heading: 25,
+                    distance: 100
+                })
+        );
+        assert!(seek.ir_beacon(0).is_none() && seek.ir_beacon(5).is_none());
+
+        let hw = MockBackend::new();
+        let mut remote = plugged(
+            &hw,
+            SensorType::INFRARED,
+            SensorConfiguration::Ir(IrSensorMode::REMOTE),
+            |s| {
+                s.ir_remote = IrRemote {
+                    channel: [0x01 | 0x10, 0, 0x0c, 0],
+                }
+            },
+        );
+        remote.read(LOOP);
+        let ch1 = remote.ir_remote(1).unwrap();
+        assert!(ch1.red_up && ch1.beacon && !ch1.red_down && !ch1.blue_up);
+        assert!(!remote.ir_remote(2).unwrap().any());
+        let ch3 = remote.ir_remote(3).unwrap();
+        assert!(ch3.blue_up && ch3.blue_down && !ch3.red_up);
+        assert!(remote.ir_remote(5).is_none());
+    }
+
+    #[test]
+    fn touch_events() {
+        let hw = MockBackend::new();
+        let mut touch = plugged(&hw, SensorType::TOUCH, SensorConfiguration::Touch, |_| {});
+        touch.read(LOOP);
+        assert!(touch.reading() == Some(SensorReading::Touch(false)));
+        assert!(!touch.touch().press_event() && !touch.touch().release_event());
+
+        hw.update_sensor(SensorPort::S1, |s| s.touch = true);
+        touch.read(LOOP);
+        assert!(touch.reading() == Some(SensorReading::Touch(true)));
+        assert!(touch.touch().press_event());
+        for _ in 0..10 {
+            touch.read(LOOP);
+        }
+        assert!(!touch.touch().press_event());
+        assert!(touch.touch().since_pressed() == Duration::from_msec(10));
+
+        hw.update_sensor(SensorPort::S1, |s| s.touch = false);
+        touch.read(LOOP);
+        assert!(touch.touch().release_event());
+        assert!(touch.touch().last_state_duration() == Duration::from_msec(10));
+    }
+
+    #[test]
+    fn nxt_readings() {
+        let period = Duration::from_msec(100);
+        let hw = MockBackend::new();
+        let mut accel = plugged(
+            &hw,
+            SensorType::HtNxtACCEL,
+            SensorConfiguration::NxtAccel,
+            |s| s.accel = [10, -200, 5],
+        );
+        accel.read(period);
+        assert!(
+            accel.reading()
+                == Some(SensorReading::Acceleration {
+                    x: 10,
+                    y: -200,
+                    z: 5
+                })
+        );
+
+        let hw = MockBackend::new();
+        let mut color = plugged(
+            &hw,
+            SensorType::HtNxtCOLOR,
+            SensorConfiguration::NxtColor,
+            |s| {
+                s.nxt_color = 9;
+                s.rgb = RgbRaw {
+                    r: 255,
+                    g: 128,
+                    b: 0,
+                };
+            },
+        );
+        color.read(period);
+        assert!(
+            color.reading()
+                == Some(SensorReading::NxtColor {
+                    color: 9,
+                    r: 255,
+                    g: 128,
+                    b: 0
+                })
+        );
+
+        let hw = MockBackend::new();
+        let mut temp = plugged(
+            &hw,
+            SensorType::NxtTEMP,
+            SensorConfiguration::NxtTemp,
+            |s| s.temp = -12.5,
+        );
+        temp.read(period);
+        assert!(temp.reading() == Some(SensorReading::Temperature(-1250)));
+
+        // an I2C sensor that stops answering gives no reading
+        hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+        temp.read(period);
+        assert!(temp.reading().is_none());
+    }
+
+    #[test]
+    fn config_errors() {
+        let hw = MockBackend::new();
+        let mut sensor = SensorData::with_backend(SensorPort::S1, &hw);
+        let reason = |sensor: &mut SensorData<&MockBackend>| {
+            sensor.attempt_cfg_apply().err().map(|e| e.reason())
+        };
+
+        sensor.configure(SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE));
+        assert!(reason(&mut sensor) == Some(ConfigErrorReason::InvalidReading));
+        assert!(!sensor.configuration_applied());
+        hw.plug_sensor(SensorPort::S1, SensorType::ULTRASONIC);
+        hw.update_sensor(SensorPort::S1, |s| s.distance = 20);
+        assert!(reason(&mut sensor).is_none());
+        assert!(sensor.configuration_applied());
+
+        // a color sensor does not answer in ultrasonic mode
+        hw.plug_sensor(SensorPort::S1, SensorType::COLOR);
+        assert!(reason(&mut sensor) == Some(ConfigErrorReason::InvalidReading));
+
+        sensor.configure(SensorConfiguration::Ultrasonic(UsSensorMode::NONE));
+        assert!(reason(&mut sensor) == Some(ConfigErrorReason::UnsupportedMode));
+        sensor.configure(SensorConfiguration::NxtUltrasonic(UsSensorMode::SINGLE));
+        let err = sensor.attempt_cfg_apply().err().unwrap();
+        assert!(
+            err == ConfigError::Sensor {
+                port: SensorPort::S1,
+                cfg: SensorConfiguration::NxtUltrasonic(UsSensorMode::SINGLE),
+                reason: ConfigErrorReason::UnsupportedMode,
+            }
+        );
+
+        // a missing NXT ultrasonic sensor does not answer
+        hw.update_sensor(SensorPort::S1, |s| s.configured = SensorType::NxtULTRASONIC);
+        assert_eq!(
+            (&hw).ultrasonic_sensor_get_distance_nxt(SensorPort::S1),
+            None
+        );
+    }
+
+    #[test]
+    fn config_report() {
+        let hw = MockBackend::new();
+        hw.plug_motor(MotorPort::B, MotorType::MEDIUM);
+        let mut ev3 = Ev3::with_backend(&hw);
+        ev3.s2().configure(SensorConfiguration::Touch);
+        ev3.s4()
+            .configure(SensorConfiguration::Ir(IrSensorMode::DISTANCE));
+        ev3.mb().configure(MotorType::MEDIUM);
+
+        let report = ev3.apply_configuration();
+        assert!(!report.is_ok());
+        assert!(report.sensor(SensorPort::S1).is_none());
+        // a touch sensor cannot be told from an empty port
+        assert!(report.sensor(SensorPort::S2).is_none());
+        assert!(
+            report.sensor(SensorPort::S4).map(|e| e.reason())
+                == Some(ConfigErrorReason::InvalidReading)
+        );
+        assert!(report.motor(MotorPort::B).is_none());
+        // S4 was retried until SENSOR_CONFIG_TIMEOUT
+        assert!(hw.utime() >= SENSOR_CONFIG_TIMEOUT.usec() as u64);
+    }
+
+    #[test]
+    fn detection() {
+        let hw = MockBackend::new();
+        hw.plug_sensor(SensorPort::S1, SensorType::COLOR);
+        hw.update_sensor(SensorPort::S1, |s| s.reflect = 30);
+        hw.plug_sensor(SensorPort::S3, SensorType::NxtULTRASONIC);
+        hw.update_sensor(SensorPort::S3, |s| s.distance = 40);
+        hw.plug_sensor(SensorPort::S4, SensorType::INFRARED);
+        hw.update_sensor(SensorPort::S4, |s| s.ir_distance = 60);
+
+        let mut ev3 = Ev3::with_backend(&hw);
+        ev3.s3()
+            .configure(SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE));
+        ev3.s4()
+            .configure(SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE));
+        let report = ev3.detect_sensors();
+
+        assert!(report.sensor(SensorPort::S1).is_none());
+        assert!(ev3.s1().configuration() == SensorConfiguration::Color(ColorSensorMode::REFLECT));
+        assert!(ev3.s1().configuration_applied());
+        // nothing answers in S2, not even as an NXT ultrasonic sensor
+        assert!(report.sensor(SensorPort::S2).is_none());
+        assert!(ev3.s2().configuration() == SensorConfiguration::None);
+        assert!(report.sensor(SensorPort::S3).is_none());
+        assert!(ev3.s3().configuration_applied());
+        assert!(
+            report.sensor(SensorPort::S4).map(|e| e.reason())
+                == Some(ConfigErrorReason::WrongDevice(SensorType::INFRARED))
+        );
+
+        ev3.read();
+        assert!(ev3.s1().reading() == Some(SensorReading::Reflect(30)));
+    }
+
+    #[test]
+    fn undetectable_sensors() {
+        assert!(!SensorConfiguration::Touch.is_detectable());
+        assert!(!SensorConfiguration::Gyro(GyroSensorMode::RATE).is_detectable());
+
+        let hw = MockBackend::new();
+        hw.plug_sensor(SensorPort::S1, SensorType::GYRO);
+        let mut ev3 = Ev3::with_backend(&hw);
+        ev3.s1()
+            .configure(SensorConfiguration::Gyro(GyroSensorMode::RATE));
+        ev3.s2().configure(SensorConfiguration::Touch);
+        let report = ev3.detect_sensors();
+        // expected but not detectable: fine unless another sensor answers
+        assert!(report.is_ok());
+        assert!(ev3.s1().configuration_applied() && ev3.s2().configuration_applied());
+    }
+
+    #[test]
+    fn filtered_readings() {
+        let hw = MockBackend::new();
+        let mut us = plugged(
+            &hw,
+            SensorType::ULTRASONIC,
+            SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE),
+            |s| s.distance = 50,
+        );
+        us.set_filters(&[SensorFilter::Glitch {
+            max_jump: 10,
+            max_held: 3,
+        }]);
+        us.read(LOOP);
+        assert_eq!(us.filtered_val(), Some(50));
+        hw.update_sensor(SensorPort::S1, |s| s.distance = -1);
+        us.read(LOOP);
+        assert!(us.reading().is_none());
+        assert_eq!(us.filtered_val(), Some(50));
+        assert!(us.filtered_reading() == Some(SensorReading::Distance(500)));
+
+        // color codes are not filtered
+        let hw = MockBackend::new();
+        let mut color = plugged(
+            &hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::COLOR),
+            |s| s.color = SensorColorCode::RED as i32,
+        );
+        color.set_filters(&[SensorFilter::MovingAverage(4)]);
+        color.read(LOOP);
+        hw.update_sensor(SensorPort::S1, |s| s.color = SensorColorCode::BLUE as i32);
+        color.read(LOOP);
+        assert!(color.filtered_reading() == Some(SensorReading::Color(Color::Blue)));
+    }
+
+    #[test]
+    fn gyro_heading_while_idle_and_driving() {
+        let hw = MockBackend::new();
+        hw.plug_sensor(SensorPort::S1, SensorType::GYRO);
+        hw.update_sensor(SensorPort::S1, |s| s.gyro_rate = 1);
+        hw.plug_motor(MotorPort::A, MotorType::LARGE);
+        let mut ev3 = Ev3::with_backend(&hw);
+        ev3.s1()
+            .configure(SensorConfiguration::Gyro(GyroSensorMode::RATE));
+        ev3.ma().configure(MotorType::LARGE);
+        assert!(ev3.s1().attempt_cfg_apply().is_ok());
+        assert!(ev3.ma().attempt_cfg_apply().is_ok());
+        ev3.s1().soft_calibration();
+        assert_eq!(ev3.s1().gyro().bias(), 1000);
+
+        // a slow turn while driving is not taken as drift
+        hw.update_sensor(SensorPort::S1, |s| s.gyro_rate = 2);
+        ev3.ma().set_power(50);
+        for _ in 0..1000 {
+            ev3.read();
+        }
+        assert!(!ev3.s1().gyro().is_stationary());
+        assert_eq!(ev3.s1().gyro().bias(), 1000);
+        assert!(ev3.s1().gyro().heading() >= 1);
+
+        // standing still, the drift is tracked
+        ev3.ma().set_power(0);
+        for _ in 0..2000 {
+            ev3.read();
+        }
+        assert!(ev3.s1().gyro().is_stationary() && ev3.s1().gyro().is_still());
+        assert!(ev3.s1().gyro().bias() > 1500);
+    }
+
+    #[test]
+    fn color_calibration() {
+        let hw = MockBackend::new();
+        let mut sensor = plugged(
+            &hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::REFLECT),
+            |s| s.reflect = 6,
+        );
+        assert!(sensor.calibrate_black());
+        hw.update_sensor(SensorPort::S1, |s| s.reflect = 86);
+        assert!(sensor.calibrate_white());
+        hw.update_sensor(SensorPort::S1, |s| s.reflect = 46);
+        sensor.read(LOOP);
+        assert_eq!(sensor.normalized_reflect(), Some(500));
+
+        // the profile is kept for RGB
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 200,
+                g: 100,
+                b: 50,
+            }
+        });
+        sensor.configure(SensorConfiguration::Color(ColorSensorMode::RGB));
+        assert!(sensor.attempt_cfg_apply().is_ok());
+        assert!(sensor.calibrate_white());
+        assert!(!sensor.calibrate_black());
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 100,
+                g: 100,
+                b: 0,
+            }
+        });
+        sensor.read(LOOP);
+        assert!(sensor.balanced_rgb() == Some((500, 1000, 0)));
+        assert_eq!(sensor.color_profile().black, 6);
+    }
+
+    // Reads until f holds, returns how long it took (None after 5s)
+    fn read_until(
+        sensor: &mut SensorData<&MockBackend>,
+        f: impl Fn(&SensorData<&MockBackend>) -> bool,
+    ) -> Option<Duration> {
+        let mut elapsed = Duration::zero();
+        while elapsed < Duration::from_msec(5000) {
+            sensor.read(LOOP);
+            elapsed += LOOP;
+            if f(sensor) {
+                return Some(elapsed);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn hot_plug() {
+        let setups: [(SensorType, SensorConfiguration); 2] = [
+            (
+                SensorType::ULTRASONIC,
+                SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE),
+            ),
+            (
+                SensorType::NxtULTRASONIC,
+                SensorConfiguration::NxtUltrasonic(UsSensorMode::DISTANCE),
+            ),
+        ];
+        for (st, cfg) in setups.iter() {
+            let hw = MockBackend::new();
+            let mut sensor = plugged(&hw, *st, *cfg, |s| s.distance = 30);
+            sensor.set_read_period(Duration::zero());
+            sensor.read(LOOP);
+            assert!(sensor.reading().is_some());
+
+            hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+            let lost_after = read_until(&mut sensor, |s| s.disconnect_event()).unwrap();
+            assert!(lost_after == SENSOR_LOST_TIMEOUT);
+            assert!(sensor.is_lost() && !sensor.configuration_applied());
+
+            // retried, but nothing there yet
+            read_until(&mut sensor, |s| s.connect_event());
+            assert!(sensor.is_lost());
+
+            hw.plug_sensor(SensorPort::S1, *st);
+            let back_after = read_until(&mut sensor, |s| s.connect_event()).unwrap();
+            assert!(back_after <= SENSOR_RETRY_PERIOD);
+            assert!(!sensor.is_lost() && sensor.configuration_applied());
+            sensor.read(LOOP);
+            assert!(sensor.reading() == Some(SensorReading::Distance(300)));
+        }
+    }
+
+    #[test]
+    fn no_unplug_without_invalid_readings() {
+        let hw = MockBackend::new();
+        let mut touch = plugged(&hw, SensorType::TOUCH, SensorConfiguration::Touch, |_| {});
+        assert!(!SensorConfiguration::Touch.shows_unplug());
+        hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+        assert!(read_until(&mut touch, |s| s.disconnect_event()).is_none());
+        assert!(!touch.is_lost());
+    }
+
+    #[test]
+    fn ultrasonic_listen() {
+        let hw = MockBackend::new();
+        let mut us = plugged(
+            &hw,
+            SensorType::ULTRASONIC,
+            SensorConfiguration::Ultrasonic(UsSensorMode::LISTEN),
+            |_| {},
+        );
+        us.read(LOOP);
+        assert!(us.reading() == Some(SensorReading::Listen(false)));
+        hw.update_sensor(SensorPort::S1, |s| s.us_listen = true);
+        us.read(LOOP);
+        assert!(us.reading() == Some(SensorReading::Listen(true)));
+        assert!(!us.trigger_measurement());
+    }
+
+    #[test]
+    fn ultrasonic_single_shot() {
+        let hw = MockBackend::new();
+        let mut us = plugged(
+            &hw,
+            SensorType::ULTRASONIC,
+            SensorConfiguration::Ultrasonic(UsSensorMode::SINGLE),
+            |s| s.distance = 42,
+        );
+        us.read(LOOP);
+        assert!(us.reading().is_none());
+
+        assert!(us.trigger_measurement());
+        let took = read_until(&mut us, |s| s.reading().is_some()).unwrap();
+        assert!(took > SENSOR_MODE_SETTLE_TIME);
+        assert!(us.reading() == Some(SensorReading::Distance(420)));
+        assert!(us.age() == Some(Duration::zero()));
+
+        // the result stays, getting older, and the quiet sensor is not lost
+        hw.update_sensor(SensorPort::S1, |s| s.distance = 10);
+        for _ in 0..1000 {
+            us.read(LOOP);
+        }
+        assert!(us.reading() == Some(SensorReading::Distance(420)));
+        assert!(us.age() == Some(Duration::from_msec(1000)));
+        assert!(!us.is_lost());
+
+        assert!(us.trigger_measurement());
+        assert!(us.reading().is_none());
+        read_until(&mut us, |s| s.reading().is_some());
+        assert!(us.reading() == Some(SensorReading::Distance(100)));
+    }
+
+    #[test]
+    fn read_periods_and_stats() {
+        let hw = MockBackend::new();
+        let mut temp = plugged(
+            &hw,
+            SensorType::NxtTEMP,
+            SensorConfiguration::NxtTemp,
+            |s| s.temp = 20.0,
+        );
+        assert!(temp.read_period() == Duration::from_msec(100));
+        for _ in 0..1000 {
+            temp.read(LOOP);
+        }
+        let stats = *temp.read_stats();
+        assert_eq!(stats.reads(), 10);
+        assert!(stats.average_period() == Duration::from_msec(100));
+        assert!(stats.min_period() == stats.max_period());
+        assert_eq!(stats.rate(), 10);
+        // between hardware reads the value only gets older
+        temp.read(LOOP);
+        assert!(temp.age() == Some(LOOP));
+
+        temp.set_read_period(Duration::from_msec(20));
+        temp.reset_read_stats();
+        for _ in 0..1000 {
+            temp.read(LOOP);
+        }
+        assert_eq!(temp.read_stats().reads(), 50);
+        assert!(temp.read_stats().last_period() == Duration::from_msec(20));
+
+        // EV3 sensors are read at every loop
+        let hw = MockBackend::new();
+        let mut touch = plugged(&hw, SensorType::TOUCH, SensorConfiguration::Touch, |_| {});
+        assert!(touch.read_period() == Duration::zero());
+        for _ in 0..100 {
+            touch.read(LOOP);
+        }
+        assert_eq!(touch.read_stats().reads(), 100);
+    }
+
+    #[test]
+    fn switch_mode_settles() {
+        let hw = MockBackend::new();
+        let mut color = plugged(
+            &hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::REFLECT),
+            |s| {
+                s.reflect = 30;
+                s.ambient = 7;
+            },
+        );
+        color.read(LOOP);
+        assert!(color
+            .switch_mode(SensorConfiguration::Color(ColorSensorMode::AMBIENT))
+            .is_ok());
+        assert!(color.is_settling());
+        let took = read_until(&mut color, |s| s.mode_ready_event()).unwrap();
+        assert!(took >= SENSOR_MODE_SETTLE_TIME);
+        assert!(!color.is_settling());
+        assert!(color.reading() == Some(SensorReading::Ambient(7)));
+
+        // the gyro bias is kept between gyro modes
+        let hw = MockBackend::new();
+        let mut gyro = plugged(
+            &hw,
+            SensorType::GYRO,
+            SensorConfiguration::Gyro(GyroSensorMode::RATE),
+            |s| s.gyro_rate = 3,
+        );
+        gyro.soft_calibration();
+        assert!(gyro
+            .switch_mode(SensorConfiguration::Gyro(GyroSensorMode::ANGLE))
+            .is_ok());
+        assert!(gyro
+            .switch_mode(SensorConfiguration::Gyro(GyroSensorMode::RATE))
+            .is_ok());
+        assert!(gyro.gyro().is_calibrated() && gyro.gyro().bias() == 3000);
+    }
+
+    #[test]
+    fn switch_mode_retries() {
+        let hw = MockBackend::new();
+        let mut sensor = plugged(
+            &hw,
+            SensorType::ULTRASONIC,
+            SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE),
+            |s| {
+                s.distance = 30;
+                s.reflect = 30;
+            },
+        );
+        let cfg = SensorConfiguration::Ultrasonic(UsSensorMode::DISTANCE);
+        assert!(sensor
+            .switch_mode(SensorConfiguration::Ultrasonic(UsSensorMode::NONE))
+            .is_err());
+        assert!(sensor.configuration() == cfg && sensor.configuration_applied());
+
+        hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+        read_until(&mut sensor, |s| s.is_lost()).unwrap();
+
+        // another sensor type, while nothing is plugged
+        let cfg = SensorConfiguration::Color(ColorSensorMode::REFLECT);
+        assert!(sensor.switch_mode(cfg).is_err());
+        assert!(sensor.is_lost());
+        hw.plug_sensor(SensorPort::S1, SensorType::COLOR);
+        read_until(&mut sensor, |s| s.connect_event()).unwrap();
+        assert!(sensor.configuration_applied());
+        sensor.read(LOOP);
+        assert!(sensor.reading() == Some(SensorReading::Reflect(30)));
+    }
+
+    #[test]
+    fn staleness() {
+        let hw = MockBackend::new();
+        let mut temp = plugged(
+            &hw,
+            SensorType::NxtTEMP,
+            SensorConfiguration::NxtTemp,
+            |s| s.temp = 20.0,
+        );
+        assert!(temp.is_stale() && temp.age().is_none());
+        read_until(&mut temp, |s| s.age().is_some()).unwrap();
+        assert!(!temp.is_stale());
+
+        temp.set_stale_threshold(Duration::from_msec(50));
+        for _ in 0..60 {
+            temp.read(LOOP);
+        }
+        assert!(temp.is_stale());
+        let (reading, age) = temp.reading_with_age().unwrap();
+        assert!(reading == SensorReading::Temperature(2000));
+        assert!(age == Duration::from_msec(60));
+
+        read_until(&mut temp, |s| s.age() == Some(Duration::zero())).unwrap();
+        assert!(!temp.is_stale());
+
+        // invalid readings do not refresh the age
+        hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+        for _ in 0..200 {
+            temp.read(LOOP);
+        }
+        assert!(temp.is_stale());
+        assert!(temp.age() == Some(Duration::from_msec(200)));
+    }
+
+    // Distance in cm and mm, from an EV3 ultrasonic sensor
+    struct Echo;
+
+    impl SensorDriver for Echo {
+        fn sensor_type(&self) -> SensorType {
+            SensorType::ULTRASONIC
+        }
+
+        fn init(&self, hw: &dyn SensorBackend, port: SensorPort) -> bool {
+            hw.ultrasonic_sensor_get_distance(port) != -1
+        }
+
+        fn read(&self, hw: &dyn SensorBackend, port: SensorPort) -> Option<[i16; 4]> {
+            match hw.ultrasonic_sensor_get_distance(port) {
+                -1 => None,
+                d => Some([d, d * 10, 0, 0]),
+            }
+        }
+
+        fn read_period(&self) -> Duration {
+            Duration::from_msec(5)
+        }
+    }
+
+    struct Other;
+
+    impl SensorDriver for Other {
+        fn sensor_type(&self) -> SensorType {
+            SensorType::ULTRASONIC
+        }
+
+        fn read(&self, _hw: &dyn SensorBackend, _port: SensorPort) -> Option<[i16; 4]> {
+            None
+        }
+    }
+
+    static ECHO: Echo = Echo;
+    static OTHER: Other = Other;
+
+    #[test]
+    fn custom_driver() {
+        let hw = MockBackend::new();
+        let mut ev3 = Ev3::with_backend(&hw);
+        let cfg = SensorConfiguration::Custom(DriverRef(&ECHO));
+        ev3.s2().configure(cfg);
+        assert!(ev3.s2().read_period() == Duration::from_msec(5));
+        assert!(
+            ev3.s2().attempt_cfg_apply().err().map(|e| e.reason())
+                == Some(ConfigErrorReason::InvalidReading)
+        );
+
+        hw.plug_sensor(SensorPort::S2, SensorType::ULTRASONIC);
+        hw.update_sensor(SensorPort::S2, |s| s.distance = 12);
+        assert!(ev3.s2().attempt_cfg_apply().is_ok());
+        while ev3.s2().read_stats().reads() == 0 {
+            ev3.read();
+        }
+        assert!(ev3.s2().reading() == Some(SensorReading::Custom([12, 120, 0, 0])));
+
+        assert!(cfg == SensorConfiguration::Custom(DriverRef(&ECHO)));
+        assert!(cfg != SensorConfiguration::Custom(DriverRef(&OTHER)));
+    }
+
+    fn rgb_sensor(hw: &MockBackend) -> SensorData<&MockBackend> {
+        let mut sensor = plugged(
+            hw,
+            SensorType::COLOR,
+            SensorConfiguration::Color(ColorSensorMode::RGB),
+            |s| {
+                s.rgb = RgbRaw {
+                    r: 1000,
+                    g: 1000,
+                    b: 1000,
+                }
+            },
+        );
+        sensor.read(LOOP);
+        sensor
+    }
+
+    #[test]
+    fn color_training() {
+        let hw = MockBackend::new();
+        let mut sensor = rgb_sensor(&hw);
+        let mut classifier = ColorClassifier::new(150);
+
+        for (label, rgb) in [(1, (900, 200, 100)), (2, (100, 300, 900))].iter() {
+            hw.update_sensor(SensorPort::S1, |s| {
+                s.rgb = RgbRaw {
+                    r: rgb.0,
+                    g: rgb.1,
+                    b: rgb.2,
+                }
+            });
+            sensor.read(LOOP);
+            assert!(sensor.train_color(&mut classifier, *label));
+        }
+
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 850,
+                g: 250,
+                b: 150,
+            }
+        });
+        sensor.read(LOOP);
+        assert_eq!(sensor.classify_color(&classifier), Some(1));
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 500,
+                g: 900,
+                b: 500,
+            }
+        });
+        sensor.read(LOOP);
+        assert_eq!(sensor.classify_color(&classifier), None);
+
+        hw.plug_sensor(SensorPort::S1, SensorType::NONE);
+        sensor.read(LOOP);
+        assert!(!sensor.train_color(&mut classifier, 3));
+    }
+
+    #[test]
+    fn sensor_history() {
+        let hw = MockBackend::new();
+        let mut temp = plugged(
+            &hw,
+            SensorType::NxtTEMP,
+            SensorConfiguration::NxtTemp,
+            |s| s.temp = 20.0,
+        );
+        let mut history = SensorHistory::<16>::new(0);
+        let mut ignored = SensorHistory::<16>::new(4);
+        for i in 0..1000 {
+            hw.update_sensor(SensorPort::S1, |s| s.temp = 20.0 + i as f32 / 1000.0);
+            temp.read(LOOP);
+            history.update(&temp, LOOP);
+            ignored.update(&temp, LOOP);
+        }
+        // one sample per hardware read, every 100ms
+        assert_eq!(history.len(), 10);
+        assert!(history.iter().nth(1).map(|(age, _)| age) == Some(Duration::from_msec(100)));
+        let rate = history.rate(Duration::from_msec(1000)).unwrap();
+        assert!((rate - 100).abs() <= 2);
+        assert!(ignored.is_empty());
+    }
+
+    #[test]
+    fn hue_detection() {
+        let hw = MockBackend::new();
+        let mut sensor = rgb_sensor(&hw);
+        let mut profile = sensor.color_profile();
+        profile.white_rgb = [400, 500, 250];
+        sensor.set_color_profile(profile);
+        let mut matcher = HueMatcher::new();
+        assert!(matcher.add(HueBand {
+            label: 3,
+            min_hue: 90,
+            max_hue: 150,
+            min_saturation: 300,
+            min_value: 200,
+        }));
+
+        // green once white balanced
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 80,
+                g: 400,
+                b: 50,
+            }
+        });
+        sensor.read(LOOP);
+        let hsv = sensor.hsv().unwrap();
+        assert!(hsv.h == 120 && hsv.s == 750 && hsv.v == 800);
+        assert!(sensor.hsl().map(|hsl| hsl.l) == Some(500));
+        let found = sensor.match_hue(&matcher).unwrap();
+        assert!(found.label == 3 && found.confidence == 600);
+
+        hw.update_sensor(SensorPort::S1, |s| {
+            s.rgb = RgbRaw {
+                r: 400,
+                g: 100,
+                b: 50,
+            }
+        });
+        sensor.read(LOOP);
+        assert!(sensor.match_hue(&matcher).is_none());
+    }
+
+    // Ev3 with a large motor in A, turning 10 deg/s per power
+    struct Plant {
+        moved: i32,
+    }
+
+    impl Plant {
+        fn ev3(hw: &MockBackend) -> Ev3<&MockBackend> {
+            hw.plug_motor(MotorPort::A, MotorType::LARGE);
+            let mut ev3 = Ev3::with_backend(hw);
+            ev3.ma().configure(MotorType::LARGE);
+            assert!(ev3.ma().attempt_cfg_apply().is_ok());
+            ev3
+        }
+
+        // called once per loop, that takes about 1ms
+        fn turn(&mut self, hw: &MockBackend) {
+            self.moved += hw.motor(MotorPort::A).power * 10;
+            hw.update_motor(MotorPort::A, |m| m.counts += self.moved / 1000);
+            self.moved %= 1000;
+        }
+    }
+
+    #[test]
+    fn motor_speed_regulation() {
+        let hw = MockBackend::new();
+        let mut ev3 = Plant::ev3(&hw);
+        let mut plant = Plant { moved: 0 };
+        // the last second averages out the encoder steps
+        let mut power = 0;
+        for i in 0..3000 {
+            ev3.read();
+            let dt = ev3.time.from_last_read();
+            ev3.ma().set_speed(-300, dt);
+            ev3.apply();
+            plant.turn(&hw);
+            if i >= 2000 {
+                power += ev3.ma().power();
+            }
+        }
+        assert_eq!(ev3.ma().target_speed(), -300);
+        assert!((ev3.ma().actual_speed() + 300).abs() <= 25);
+        assert!(ev3.ma().actual_speed() == ev3.ma().speed());
+        assert!((power / 1000 + 30).abs() <= 1);
+    }
+
+    #[test]
+    fn position_control_limits() {
+        let hw = MockBackend::new();
+        let mut ev3 = Plant::ev3(&hw);
+        let mut plant = Plant { moved: 0 };
+        ev3.ma().set_position_config(PositionConfig {
+            max_error: -80,
+            max_integral: -400,
+            max_power: -50,
+            ..PositionConfig::new()
+        });
+        assert_eq!(ev3.ma().position_config().max_power, 50);
+        for _ in 0..3000 {
+            ev3.read();
+            let dt = ev3.time.from_last_read();
+            ev3.ma().set_power_to_position(-360, dt);
+            assert!(ev3.ma().power().abs() <= 50);
+            ev3.apply();
+            plant.turn(&hw);
+        }
+        assert!((ev3.ma().position() + 360).abs() <= 2);
+
+        ev3.ma().set_position_config(PositionConfig {
+            max_error: i32::MIN,
+            ..PositionConfig::new()
+        });
+        assert_eq!(ev3.ma().position_config().max_error, i32::MAX);
+    }
+
+    #[test]
+    fn profiled_moves() {
+        let hw = MockBackend::new();
+        let mut ev3 = Plant::ev3(&hw);
+        let mut plant = Plant { moved: 0 };
+        ev3.read();
+        ev3.ma()
+            .start_move(180, 200, 400, ProfileShape::Trapezoidal);
+        let mut done_at = None;
+        for i in 0..3000 {
+            ev3.read();
+            let dt = ev3.time.from_last_read();
+            ev3.ma().update_move(dt);
+            ev3.apply();
+            plant.turn(&hw);
+            if ev3.ma().move_done_event() {
+                done_at = Some(i);
+            }
+        }
+        // 1.4s by the profile
+        assert!((1399..1700).contains(&done_at.unwrap()));
+        assert!(ev3.ma().is_move_done());
+        assert!((ev3.ma().position() - 180).abs() <= MOVE_TOLERANCE);
+
+        // a very slow move starts slowly too
+        ev3.ma()
+            .start_move(20000, 5, 100, ProfileShape::Trapezoidal);
+        for _ in 0..1000 {
+            ev3.read();
+            let dt = ev3.time.from_last_read();
+            ev3.ma().update_move(dt);
+            assert!(ev3.ma().power().abs() < 20);
+            ev3.apply();
+            plant.turn(&hw);
+        }
+        assert!(!ev3.ma().is_move_done());
+    }
+
+    #[test]
+    fn motor_stall() {
+        let hw = MockBackend::new();
+        let mut ev3 = Plant::ev3(&hw);
+        let mut plant = Plant { moved: 0 };
+        ev3.ma().set_stall_config(StallConfig {
+            cut_power: true,
+            ..StallConfig::new()
+        });
+
+        // a free motor does not stall
+        ev3.ma().set_power(60);
+        for _ in 0..1000 {
+            ev3.read();
+            assert!(!ev3.ma().is_stalled());
+            ev3.apply();
+            plant.turn(&hw);
+        }
+
+        // blocked: it stalls once after StallConfig::time
+        let mut stalled_at = None;
+        for i in 0..1000 {
+            ev3.read();
+            if ev3.ma().stall_event() {
+                assert!(stalled_at.is_none());
+                stalled_at = Some(i);
+            }
+            ev3.apply();
+        }
+        assert!((500..700).contains(&stalled_at.unwrap()));
+        assert!(ev3.ma().is_stalled());
+        assert!(hw.motor(MotorPort::A).power == 0);
+
+        // power comes back with reset_stall
+        ev3.ma().reset_stall();
+        ev3.read();
+        ev3.apply();
+        assert!(!ev3.ma().is_stalled());
+        assert!(hw.motor(MotorPort::A).power == 60);
+    }
+
+    #[test]
+    fn motor_speed_estimate() {
+        let hw = MockBackend::new();
+        let mut ev3 = Plant::ev3(&hw);
+        let mut plant = Plant { moved: 0 };
+        ev3.ma().set_power(-30);
+        for _ in 0..500 {
+            ev3.read();
+            ev3.apply();
+            plant.turn(&hw);
+        }
+        // 300 deg/s, within one encoder step over the window
+        assert!((ev3.ma().speed() + 300).abs() <= 20);
+
+        // back to zero once the window holds no movement
+        ev3.ma().stop(true);
+        for _ in 0..100 {
+            ev3.read();
+            ev3.apply();
+            plant.turn(&hw);
+        }
+        assert_eq!(ev3.ma().speed(), 0);
+    }
+}
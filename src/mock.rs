@@ -0,0 +1,440 @@
+// In-memory brick, to run robot logic (and the whole Ev3 read/apply loop)
+// on the host.
+//
+//     let hw = MockBackend::new();
+//     hw.plug_sensor(SensorPort::S1, SensorType::GYRO);
+//     let mut ev3 = Ev3::with_backend(&hw);
+//
+// It behaves like ev3rt: configuring a port always succeeds and
+// sensor_get_type only echoes the configured type. A sensor that is missing
+// (or of another type than configured) reads as all ones (-1, 0xff,
+// 0xffff), or does not answer at all on I2C; a missing motor never turns.
+
+use core::cell::Cell;
+
+use crate::{
+    Backend, Button, IrRemote, IrSeek, LcdColor, LcdFont, LedColor, MotorPort, MotorType, RgbRaw,
+    SensorBackend, SensorColorCode, SensorPort, SensorType, ER,
+};
+
+#[derive(Clone, Copy)]
+pub struct MockSensor {
+    pub plugged: SensorType,
+    pub configured: SensorType,
+    pub color: i32,
+    pub reflect: u8,
+    pub ambient: u8,
+    pub rgb: RgbRaw,
+    pub gyro_angle: i16,
+    pub gyro_rate: i16,
+    pub distance: i16,
+    pub us_listen: bool,
+    pub ir_distance: u8,
+    pub ir_seek: IrSeek,
+    pub ir_remote: IrRemote,
+    pub touch: bool,
+    pub accel: [i16; 3],
+    pub nxt_color: u8,
+    pub temp: f32,
+}
+
+impl MockSensor {
+    pub fn new() -> Self {
+        Self {
+            plugged: SensorType::NONE,
+            configured: SensorType::NONE,
+            color: SensorColorCode::NONE as i32,
+            reflect: 0,
+            ambient: 0,
+            rgb: RgbRaw { r: 0, g: 0, b: 0 },
+            gyro_angle: 0,
+            gyro_rate: 0,
+            distance: 0,
+            us_listen: false,
+            ir_distance: 0,
+            ir_seek: IrSeek {
+                heading: [0; 4],
+                distance: [-128; 4],
+            },
+            ir_remote: IrRemote { channel: [0; 4] },
+            touch: false,
+            accel: [0; 3],
+            nxt_color: 0,
+            temp: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MockMotor {
+    pub plugged: MotorType,
+    pub configured: MotorType,
+    pub counts: i32,
+    pub power: i32,
+    pub braked: bool,
+}
+
+impl MockMotor {
+    pub fn new() -> Self {
+        Self {
+            plugged: MotorType::NONE,
+            configured: MotorType::NONE,
+            counts: 0,
+            power: 0,
+            braked: false,
+        }
+    }
+}
+
+pub struct MockBackend {
+    sensors: [Cell<MockSensor>; 4],
+    motors: [Cell<MockMotor>; 4],
+    buttons: [Cell<bool>; 6],
+    led: Cell<LedColor>,
+    font: Cell<LcdFont>,
+    lines: Cell<u32>,
+    rects: Cell<u32>,
+    strings: Cell<u32>,
+    utime: Cell<u64>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self {
+            sensors: [
+                Cell::new(MockSensor::new()),
+                Cell::new(MockSensor::new()),
+                Cell::new(MockSensor::new()),
+                Cell::new(MockSensor::new()),
+            ],
+            motors: [
+                Cell::new(MockMotor::new()),
+                Cell::new(MockMotor::new()),
+                Cell::new(MockMotor::new()),
+                Cell::new(MockMotor::new()),
+            ],
+            buttons: [
+                Cell::new(false),
+                Cell::new(false),
+                Cell::new(false),
+                Cell::new(false),
+                Cell::new(false),
+                Cell::new(false),
+            ],
+            led: Cell::new(LedColor::OFF),
+            font: Cell::new(LcdFont::MEDIUM),
+            lines: Cell::new(0),
+            rects: Cell::new(0),
+            strings: Cell::new(0),
+            utime: Cell::new(0),
+        }
+    }
+
+    pub fn sensor(&self, port: SensorPort) -> MockSensor {
+        self.sensors[port as usize].get()
+    }
+
+    pub fn set_sensor(&self, port: SensorPort, sensor: MockSensor) {
+        self.sensors[port as usize].set(sensor);
+    }
+
+    pub fn update_sensor(&self, port: SensorPort, f: impl FnOnce(&mut MockSensor)) {
+        let mut sensor = self.sensor(port);
+        f(&mut sensor);
+        self.set_sensor(port, sensor);
+    }
+
+    pub fn plug_sensor(&self, port: SensorPort, st: SensorType) {
+        self.update_sensor(port, |s| s.plugged = st);
+    }
+
+    pub fn motor(&self, port: MotorPort) -> MockMotor {
+        self.motors[port as usize].get()
+    }
+
+    pub fn set_motor(&self, port: MotorPort, motor: MockMotor) {
+        self.motors[port as usize].set(motor);
+    }
+
+    pub fn update_motor(&self, port: MotorPort, f: impl FnOnce(&mut MockMotor)) {
+        let mut motor = self.motor(port);
+        f(&mut motor);
+        self.set_motor(port, motor);
+    }
+
+    pub fn plug_motor(&self, port: MotorPort, mt: MotorType) {
+        self.update_motor(port, |m| m.plugged = mt);
+    }
+
+    pub fn set_button(&self, button: Button, pressed: bool) {
+        self.buttons[button as usize].set(pressed);
+    }
+
+    pub fn led(&self) -> LedColor {
+        self.led.get()
+    }
+
+    pub fn font(&self) -> LcdFont {
+        self.font.get()
+    }
+
+    pub fn lines_drawn(&self) -> u32 {
+        self.lines.get()
+    }
+
+    pub fn rects_filled(&self) -> u32 {
+        self.rects.get()
+    }
+
+    pub fn strings_drawn(&self) -> u32 {
+        self.strings.get()
+    }
+
+    pub fn utime(&self) -> u64 {
+        self.utime.get()
+    }
+
+    pub fn advance_usec(&self, usec: u64) {
+        self.utime.set(self.utime.get() + usec);
+    }
+
+    // What the sensor driver sees: the plugged sensor if it is the
+    // configured one, otherwise all ones
+    fn live_sensor(&self, port: SensorPort) -> MockSensor {
+        let sensor = self.sensor(port);
+        if sensor.plugged != SensorType::NONE && sensor.plugged == sensor.configured {
+            sensor
+        } else {
+            MockSensor {
+                plugged: sensor.plugged,
+                configured: sensor.configured,
+                color: SensorColorCode::NONE as i32,
+                reflect: 0xff,
+                ambient: 0xff,
+                rgb: RgbRaw {
+                    r: 0xffff,
+                    g: 0xffff,
+                    b: 0xffff,
+                },
+                gyro_angle: -1,
+                gyro_rate: -1,
+                distance: -1,
+                us_listen: false,
+                ir_distance: 0xff,
+                ir_seek: IrSeek {
+                    heading: [-1; 4],
+                    distance: [-1; 4],
+                },
+                ir_remote: IrRemote { channel: [0xff; 4] },
+                touch: false,
+                accel: [-1; 3],
+                nxt_color: 0xff,
+                temp: 0.0,
+            }
+        }
+    }
+
+    // I2C sensors answer only when they are there
+    fn i2c_sensor(&self, port: SensorPort) -> Option<MockSensor> {
+        let sensor = self.sensor(port);
+        if sensor.plugged != SensorType::NONE && sensor.plugged == sensor.configured {
+            Some(sensor)
+        } else {
+            None
+        }
+    }
+
+    fn color_code(code: i32) -> SensorColorCode {
+        match code {
+            1 => SensorColorCode::BLACK,
+            2 => SensorColorCode::BLUE,
+            3 => SensorColorCode::GREEN,
+            4 => SensorColorCode::YELLOW,
+            5 => SensorColorCode::RED,
+            6 => SensorColorCode::WHITE,
+            7 => SensorColorCode::BROWN,
+            _ => SensorColorCode::NONE,
+        }
+    }
+}
+
+impl SensorBackend for &MockBackend {
+    fn sensor_config(&self, port: SensorPort, st: SensorType) -> ER {
+        self.update_sensor(port, |s| s.configured = st);
+        ER::OK
+    }
+
+    fn sensor_get_type(&self, port: SensorPort) -> SensorType {
+        self.sensor(port).configured
+    }
+
+    fn color_sensor_get_color(&self, port: SensorPort) -> SensorColorCode {
+        MockBackend::color_code(self.live_sensor(port).color)
+    }
+
+    fn color_sensor_get_reflect(&self, port: SensorPort) -> u8 {
+        self.live_sensor(port).reflect
+    }
+
+    fn color_sensor_get_ambient(&self, port: SensorPort) -> u8 {
+        self.live_sensor(port).ambient
+    }
+
+    fn color_sensor_get_rgb(&self, port: SensorPort) -> RgbRaw {
+        self.live_sensor(port).rgb
+    }
+
+    fn gyro_sensor_get_angle(&self, port: SensorPort) -> i16 {
+        self.live_sensor(port).gyro_angle
+    }
+
+    fn gyro_sensor_get_rate(&self, port: SensorPort) -> i16 {
+        self.live_sensor(port).gyro_rate
+    }
+
+    fn gyro_sensor_reset(&self, port: SensorPort) -> ER {
+        self.update_sensor(port, |s| s.gyro_angle = 0);
+        ER::OK
+    }
+
+    fn ultrasonic_sensor_get_distance(&self, port: SensorPort) -> i16 {
+        self.live_sensor(port).distance
+    }
+
+    fn ultrasonic_sensor_get_distance_nxt(&self, port: SensorPort) -> Option<i16> {
+        self.i2c_sensor(port).map(|s| s.distance)
+    }
+
+    fn ultrasonic_sensor_listen(&self, port: SensorPort) -> bool {
+        self.live_sensor(port).us_listen
+    }
+
+    fn infrared_sensor_get_distance(&self, port: SensorPort) -> u8 {
+        self.live_sensor(port).ir_distance
+    }
+
+    fn infrared_sensor_seek(&self, port: SensorPort) -> IrSeek {
+        self.live_sensor(port).ir_seek
+    }
+
+    fn infrared_sensor_get_remote(&self, port: SensorPort) -> IrRemote {
+        self.live_sensor(port).ir_remote
+    }
+
+    fn touch_sensor_is_pressed(&self, port: SensorPort) -> bool {
+        self.live_sensor(port).touch
+    }
+
+    fn ht_nxt_accel_sensor_measure(&self, port: SensorPort) -> Option<[i16; 3]> {
+        self.i2c_sensor(port).map(|s| s.accel)
+    }
+
+    fn ht_nxt_color_sensor_measure_color(&self, port: SensorPort) -> Option<u8> {
+        self.i2c_sensor(port).map(|s| s.nxt_color)
+    }
+
+    fn ht_nxt_color_sensor_measure_rgb(&self, port: SensorPort) -> Option<RgbRaw> {
+        self.i2c_sensor(port).map(|s| s.rgb)
+    }
+
+    fn nxt_temp_sensor_measure(&self, port: SensorPort) -> Option<f32> {
+        self.i2c_sensor(port).map(|s| s.temp)
+    }
+}
+
+impl Backend for &MockBackend {
+    fn motor_config(&self, port: MotorPort, mt: MotorType) -> ER {
+        self.update_motor(port, |m| m.configured = mt);
+        ER::OK
+    }
+
+    fn motor_get_counts(&self, port: MotorPort) -> i32 {
+        let motor = self.motor(port);
+        if motor.plugged != MotorType::NONE && motor.plugged == motor.configured {
+            motor.counts
+        } else {
+            0
+        }
+    }
+
+    fn motor_reset_counts(&self, port: MotorPort) -> ER {
+        self.update_motor(port, |m| m.counts = 0);
+        ER::OK
+    }
+
+    fn motor_set_power(&self, port: MotorPort, power: i32) -> ER {
+        self.update_motor(port, |m| {
+            m.power = power;
+            m.braked = false;
+        });
+        ER::OK
+    }
+
+    fn motor_stop(&self, port: MotorPort, brake: bool) -> ER {
+        self.update_motor(port, |m| {
+            m.power = 0;
+            m.braked = brake;
+        });
+        ER::OK
+    }
+
+    fn lcd_set_font(&self, font: LcdFont) -> ER {
+        self.font.set(font);
+        ER::OK
+    }
+
+    fn lcd_draw_string(&self, _s: &str, _x: i32, _y: i32) -> ER {
+        self.strings.set(self.strings.get() + 1);
+        ER::OK
+    }
+
+    fn lcd_draw_line(&self, _x0: i32, _y0: i32, _x1: i32, _y1: i32) -> ER {
+        self.lines.set(self.lines.get() + 1);
+        ER::OK
+    }
+
+    fn lcd_fill_rect(&self, _x: i32, _y: i32, _w: i32, _h: i32, _color: LcdColor) -> ER {
+        self.rects.set(self.rects.get() + 1);
+        ER::OK
+    }
+
+    fn led_set_color(&self, color: LedColor) -> ER {
+        self.led.set(color);
+        ER::OK
+    }
+
+    fn button_is_pressed(&self, button: Button) -> bool {
+        self.buttons[button as usize].get()
+    }
+
+    fn get_utime(&self) -> u64 {
+        self.utime.get()
+    }
+
+    fn msleep(&self, ms: i32) -> ER {
+        self.advance_usec(ms as u64 * 1_000);
+        ER::OK
+    }
+
+    fn abort(&self) -> ! {
+        panic!("ev3 abort")
+    }
+}
+
+impl Default for MockSensor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for MockMotor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,131 @@
+// Tuning of MotorData::set_power_to_position.
+// Gains are in 1/1000 power per deg (kp), per deg*s (ki) and per deg/s
+// (kd). Errors beyond outer_zone use outer_kp from there on, errors within
+// deadband count as none, and errors beyond max_error as max_error.
+// max_integral is in deg*ms.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PositionConfig {
+    pub kp: i32,
+    pub ki: i32,
+    pub kd: i32,
+    pub outer_zone: i32,
+    pub outer_kp: i32,
+    pub deadband: i32,
+    pub max_error: i32,
+    pub max_integral: i32,
+    pub max_power: i32,
+}
+
+impl PositionConfig {
+    pub const fn new() -> Self {
+        Self {
+            kp: 2000,
+            ki: 25000,
+            kd: 0,
+            outer_zone: 10,
+            outer_kp: 4000,
+            deadband: 0,
+            max_error: 80,
+            max_integral: 400,
+            max_power: 100,
+        }
+    }
+
+    // Proportional part for an error that is already clamped
+    pub fn proportional(&self, diff: i32) -> i32 {
+        let outer_min = self.outer_zone * self.kp / 1000;
+        if diff >= self.outer_zone {
+            outer_min + (diff - self.outer_zone) * self.outer_kp / 1000
+        } else if diff <= -self.outer_zone {
+            -outer_min + (diff + self.outer_zone) * self.outer_kp / 1000
+        } else {
+            diff * self.kp / 1000
+        }
+    }
+}
+
+impl Default for PositionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Zone table of MotorData::set_power_to_position_fixed: full outer_power
+// beyond outer_zone, error * middle_factor down to inner_zone, then
+// error * inner_factor.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PositionZones {
+    pub outer_power: i32,
+    pub outer_zone: i32,
+    pub middle_factor: i32,
+    pub inner_zone: i32,
+    pub inner_factor: i32,
+}
+
+impl PositionZones {
+    pub const fn new() -> Self {
+        Self {
+            outer_power: 60,
+            outer_zone: 12,
+            middle_factor: 2,
+            inner_zone: 8,
+            inner_factor: 1,
+        }
+    }
+
+    // Stiffer, for heavier mechanisms
+    pub const fn strong() -> Self {
+        Self {
+            outer_power: 80,
+            outer_zone: 12,
+            middle_factor: 3,
+            inner_zone: 8,
+            inner_factor: 2,
+        }
+    }
+
+    pub fn power(&self, diff: i32) -> i32 {
+        if diff > self.outer_zone {
+            self.outer_power
+        } else if diff > self.inner_zone {
+            diff * self.middle_factor
+        } else if diff < -self.outer_zone {
+            -self.outer_power
+        } else if diff < -self.inner_zone {
+            diff * self.middle_factor
+        } else {
+            diff * self.inner_factor
+        }
+    }
+}
+
+impl Default for PositionZones {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proportional_zones() {
+        let cfg = PositionConfig::new();
+        assert_eq!(cfg.proportional(5), 10);
+        assert_eq!(cfg.proportional(10), 20);
+        assert_eq!(cfg.proportional(20), 60);
+        assert_eq!(cfg.proportional(-20), -60);
+    }
+
+    #[test]
+    fn zone_table() {
+        let zones = PositionZones::new();
+        assert_eq!(zones.power(100), 60);
+        assert_eq!(zones.power(10), 20);
+        assert_eq!(zones.power(3), 3);
+        assert_eq!(zones.power(-3), -3);
+        assert_eq!(zones.power(-10), -20);
+        assert_eq!(zones.power(-100), -60);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::{isqrt, Duration};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ProfileShape {
+    // constant acceleration up to the cruise speed and down again
+    Trapezoidal,
+    // acceleration that grows and fades smoothly (no jerk at the corners);
+    // it peaks at the given acceleration, so ramps take 1.5 times as long
+    SCurve,
+}
+
+// A move from start to target that reaches at most max_speed (deg/s) and
+// accelerates at most at acceleration (deg/s^2), as positions over time.
+#[derive(Clone, Copy)]
+pub struct MotionProfile {
+    start: i32,
+    target: i32,
+    shape: ProfileShape,
+    // deg/s
+    peak_speed: i64,
+    // usec
+    ramp_time: i64,
+    cruise_time: i64,
+    elapsed: i64,
+}
+
+impl MotionProfile {
+    pub fn new(
+        start: i32,
+        target: i32,
+        max_speed: i32,
+        acceleration: i32,
+        shape: ProfileShape,
+    ) -> Self {
+        let distance = (target - start).abs() as i64;
+        let max_speed = max_speed.max(1) as i64;
+        let acceleration = acceleration.max(1) as i64;
+
+        // ramp time and distance for a given peak speed
+        let ramp_time = |v: i64| match shape {
+            ProfileShape::Trapezoidal => v * 1_000_000 / acceleration,
+            ProfileShape::SCurve => v * 1_500_000 / acceleration,
+        };
+        let ramp_distance = |v: i64| v * ramp_time(v) / 2_000_000;
+
+        let peak_speed = if 2 * ramp_distance(max_speed) <= distance {
+            max_speed
+        } else {
+            // never reaches max_speed
+            match shape {
+                ProfileShape::Trapezoidal => isqrt(distance * acceleration),
+                ProfileShape::SCurve => isqrt(distance * acceleration * 2 / 3),
+            }
+            .max(1)
+        };
+        let ramp = ramp_time(peak_speed);
+        let cruise = (distance * 1_000_000 / peak_speed - ramp).max(0);
+
+        Self {
+            start,
+            target,
+            shape,
+            peak_speed,
+            ramp_time: ramp,
+            cruise_time: cruise,
+            elapsed: 0,
+        }
+    }
+
+    pub fn start(&self) -> i32 {
+        self.start
+    }
+
+    pub fn target(&self) -> i32 {
+        self.target
+    }
+
+    pub fn shape(&self) -> ProfileShape {
+        self.shape
+    }
+
+    // usec, can be longer than a Duration holds
+    fn total_time(&self) -> i64 {
+        2 * self.ramp_time + self.cruise_time
+    }
+
+    // Saturates at the longest Duration (about 35 minutes)
+    pub fn duration(&self) -> Duration {
+        Duration::from_usec(self.total_time().min(i32::MAX as i64) as i32)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_usec(self.elapsed.min(i32::MAX as i64) as i32)
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        self.elapsed = (self.elapsed + dt.usec() as i64).min(self.total_time());
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.total_time()
+    }
+
+    // Distance covered after t usec of ramp, in 1/1000 deg
+    fn ramp_position(&self, t: i64) -> i64 {
+        let (v, ta, t) = (
+            self.peak_speed as i128,
+            self.ramp_time.max(1) as i128,
+            t as i128,
+        );
+        let p = match self.shape {
+            ProfileShape::Trapezoidal => v * t * t / (2 * ta),
+            ProfileShape::SCurve => {
+                v * t * t * t / (ta * ta) - v * t * t * t * t / (2 * ta * ta * ta)
+            }
+        };
+        (p / 1000) as i64
+    }
+
+    // deg/s after t usec of ramp
+    fn ramp_speed(&self, t: i64) -> i64 {
+        let (v, ta, t) = (
+            self.peak_speed as i128,
+            self.ramp_time.max(1) as i128,
+            t as i128,
+        );
+        let s = match self.shape {
+            ProfileShape::Trapezoidal => v * t / ta,
+            ProfileShape::SCurve => v * (3 * t * t * ta - 2 * t * t * t) / (ta * ta * ta),
+        };
+        s as i64
+    }
+
+    // Distance from start in 1/1000 deg, always positive
+    fn covered(&self) -> i64 {
+        let t = self.elapsed;
+        let ramp = self.ramp_position(self.ramp_time);
+        let total = 2 * ramp + self.peak_speed * self.cruise_time / 1000;
+        if t < self.ramp_time {
+            self.ramp_position(t)
+        } else if t < self.ramp_time + self.cruise_time {
+            ramp + self.peak_speed * (t - self.ramp_time) / 1000
+        } else if !self.is_finished() {
+            total - self.ramp_position(self.total_time() - t)
+        } else {
+            total
+        }
+    }
+
+    // Where the motor should be now
+    pub fn position(&self) -> i32 {
+        if self.is_finished() {
+            return self.target;
+        }
+        let covered = (self.covered() / 1000) as i32;
+        if self.target >= self.start {
+            (self.start + covered).min(self.target)
+        } else {
+            (self.start - covered).max(self.target)
+        }
+    }
+
+    // deg/s, negative when moving backwards
+    pub fn speed(&self) -> i32 {
+        let t = self.elapsed;
+        let speed = if t < self.ramp_time {
+            self.ramp_speed(t)
+        } else if t < self.ramp_time + self.cruise_time {
+            self.peak_speed
+        } else if !self.is_finished() {
+            self.ramp_speed(self.total_time() - t)
+        } else {
+            0
+        } as i32;
+        if self.target >= self.start {
+            speed
+        } else {
+            -speed
+        }
+    }
+
+    // 0-1000, by distance
+    pub fn progress(&self) -> i32 {
+        let distance = (self.target - self.start).abs();
+        if distance == 0 {
+            return 1000;
+        }
+        (self.position() - self.start).abs() * 1000 / distance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trapezoidal() {
+        let mut profile = MotionProfile::new(0, 1000, 500, 1000, ProfileShape::Trapezoidal);
+        // 0.5s ramps of 125 deg, 1.5s at 500 deg/s
+        assert!(profile.duration() == Duration::from_msec(2500));
+        profile.advance(Duration::from_msec(500));
+        assert_eq!(profile.position(), 125);
+        assert_eq!(profile.speed(), 500);
+        profile.advance(Duration::from_msec(750));
+        assert_eq!(profile.progress(), 500);
+        profile.advance(Duration::from_msec(2000));
+        assert!(profile.is_finished());
+        assert_eq!(profile.position(), 1000);
+        assert_eq!(profile.speed(), 0);
+    }
+
+    #[test]
+    fn short_move_backwards() {
+        let mut profile = MotionProfile::new(100, 0, 1000, 1000, ProfileShape::Trapezoidal);
+        // too short for the cruise speed: 10 deg ramps peaking at 316 deg/s
+        assert!((profile.duration().usec() - 632_000).abs() < 1000);
+        profile.advance(Duration::from_usec(316_000));
+        assert_eq!(profile.speed(), -316);
+        assert!((profile.position() - 50).abs() <= 1);
+        profile.advance(Duration::from_msec(320));
+        assert!(profile.is_finished());
+        assert_eq!(profile.position(), 0);
+    }
+
+    #[test]
+    fn s_curve_is_monotonic() {
+        let mut profile = MotionProfile::new(0, 360, 300, 600, ProfileShape::SCurve);
+        let mut last = 0;
+        while !profile.is_finished() {
+            profile.advance(Duration::from_msec(10));
+            assert!(profile.position() >= last);
+            assert!(profile.speed() <= 300);
+            last = profile.position();
+        }
+        assert_eq!(last, 360);
+    }
+
+    #[test]
+    fn longer_than_a_duration() {
+        // 4000s at 5 deg/s
+        let mut profile = MotionProfile::new(0, 20000, 5, 100, ProfileShape::Trapezoidal);
+        assert!(profile.duration().usec() == i32::MAX);
+        profile.advance(Duration::from_msec(2000));
+        assert!(!profile.is_finished());
+        assert_eq!(profile.speed(), 5);
+        assert_eq!(profile.position(), 9);
+        for _ in 0..2000 {
+            profile.advance(Duration::from_msec(2000));
+        }
+        assert!(profile.is_finished());
+        assert_eq!(profile.position(), 20000);
+    }
+}
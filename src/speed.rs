@@ -0,0 +1,274 @@
+use crate::Duration;
+
+// Gains of the speed regulator, in power per 1000 deg/s of target speed
+// (kf), of speed error (kp) and per 1000 deg of accumulated error (ki).
+// max_acceleration (deg/s^2) limits how fast the target is approached,
+// 0 means no limit.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpeedConfig {
+    pub kf: i32,
+    pub kp: i32,
+    pub ki: i32,
+    pub max_acceleration: i32,
+}
+
+impl SpeedConfig {
+    // Good enough for an unloaded EV3 large motor (about 1000 deg/s at full
+    // power)
+    pub const fn new() -> Self {
+        Self {
+            kf: 100,
+            kp: 200,
+            ki: 300,
+            max_acceleration: 2000,
+        }
+    }
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Feed forward + PI regulation of the motor speed measured by
+// SpeedEstimator.
+// Speeds are kept in 1/1000 deg/s.
+#[derive(Clone, Copy)]
+pub struct SpeedRegulator {
+    cfg: SpeedConfig,
+    active: bool,
+    target: i32,
+    setpoint: i32,
+    speed: i32,
+    integral: i64,
+}
+
+impl SpeedRegulator {
+    pub fn new() -> Self {
+        Self {
+            cfg: SpeedConfig::new(),
+            active: false,
+            target: 0,
+            setpoint: 0,
+            speed: 0,
+            integral: 0,
+        }
+    }
+
+    pub fn config(&self) -> SpeedConfig {
+        self.cfg
+    }
+
+    pub fn set_config(&mut self, cfg: SpeedConfig) {
+        self.cfg = cfg;
+        self.integral = 0;
+    }
+
+    // Forgets the state: the next update starts from standstill
+    pub fn reset(&mut self) {
+        self.active = false;
+        self.setpoint = 0;
+        self.speed = 0;
+        self.integral = 0;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // deg/s
+    pub fn target(&self) -> i32 {
+        self.target / 1000
+    }
+
+    // The target after the acceleration limit, deg/s
+    pub fn setpoint(&self) -> i32 {
+        self.setpoint / 1000
+    }
+
+    // Measured speed, deg/s
+    pub fn speed(&self) -> i32 {
+        self.speed / 1000
+    }
+
+    // Takes the target and the measured speed (deg/s, MotorData::speed) and
+    // the time since the previous update, returns the power to apply.
+    pub fn update(&mut self, target: i32, speed: i32, dt: Duration) -> i32 {
+        let dt = dt.usec() as i64;
+        self.target = target * 1000;
+        self.speed = speed * 1000;
+        self.active = true;
+
+        self.setpoint = if self.cfg.max_acceleration > 0 {
+            let step = (self.cfg.max_acceleration as i64 * dt / 1000) as i32;
+            self.target
+                .clamp(self.setpoint - step, self.setpoint + step)
+        } else {
+            self.target
+        };
+
+        let err = (self.setpoint - self.speed) as i64;
+        self.integral += err * dt / 1_000_000;
+        if self.cfg.ki > 0 {
+            let limit = 100 * 1_000_000 / self.cfg.ki as i64;
+            self.integral = self.integral.clamp(-limit, limit);
+        }
+
+        let pwr = self.setpoint as i64 * self.cfg.kf as i64 / 1_000_000
+            + err * self.cfg.kp as i64 / 1_000_000
+            + self.integral * self.cfg.ki as i64 / 1_000_000;
+        pwr.clamp(-100, 100) as i32
+    }
+}
+
+impl Default for SpeedRegulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Samples kept by SpeedEstimator. They are spread over the window, so that
+// any window is covered whatever the read rate.
+pub const SPEED_ESTIMATOR_SAMPLES: usize = 16;
+
+// Motor speed from encoder counts: the distance covered over the last
+// window (at least one read back) divided by its duration. A longer window
+// hides the 1 deg steps of the encoder but lags more.
+#[derive(Clone, Copy)]
+pub struct SpeedEstimator {
+    window: Duration,
+    positions: [i32; SPEED_ESTIMATOR_SAMPLES],
+    // time from the previous sample
+    periods: [Duration; SPEED_ESTIMATOR_SAMPLES],
+    next: usize,
+    count: usize,
+    // time from the newest sample
+    pending: Duration,
+    speed: i32,
+}
+
+impl SpeedEstimator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            positions: [0; SPEED_ESTIMATOR_SAMPLES],
+            periods: [Duration::zero(); SPEED_ESTIMATOR_SAMPLES],
+            next: 0,
+            count: 0,
+            pending: Duration::zero(),
+            speed: 0,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    // Forgets the samples: the speed is 0 until the second update
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.pending = Duration::zero();
+        self.speed = 0;
+    }
+
+    // deg/s
+    pub fn speed(&self) -> i32 {
+        self.speed
+    }
+
+    pub fn update(&mut self, pos: i32, dt: Duration) {
+        self.pending += dt;
+
+        // walk back from the newest sample until the window is covered
+        let mut span = self.pending;
+        let mut idx = (self.next + SPEED_ESTIMATOR_SAMPLES - 1) % SPEED_ESTIMATOR_SAMPLES;
+        for i in 0..self.count {
+            if span >= self.window || i + 1 == self.count {
+                break;
+            }
+            span += self.periods[idx];
+            idx = (idx + SPEED_ESTIMATOR_SAMPLES - 1) % SPEED_ESTIMATOR_SAMPLES;
+        }
+        if self.count > 0 && span.usec() > 0 {
+            let distance = (pos - self.positions[idx]) as i64;
+            self.speed = (distance * 1_000_000 / span.usec() as i64) as i32;
+        }
+
+        let spacing = self.window.usec() / (SPEED_ESTIMATOR_SAMPLES as i32 - 1);
+        if self.count == 0 || self.pending.usec() >= spacing {
+            self.positions[self.next] = pos;
+            self.periods[self.next] = self.pending;
+            self.next = (self.next + 1) % SPEED_ESTIMATOR_SAMPLES;
+            if self.count < SPEED_ESTIMATOR_SAMPLES {
+                self.count += 1;
+            }
+            self.pending = Duration::zero();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_msec(1);
+
+    #[test]
+    fn regulator_converges() {
+        let mut regulator = SpeedRegulator::new();
+        let mut estimator = SpeedEstimator::new(Duration::from_msec(50));
+        // motor turning at 10 deg/s per power, in 1/1000 deg
+        let mut pos = 0i32;
+        let mut pwr = 0;
+        for _ in 0..2000 {
+            pos += pwr * 10;
+            estimator.update(pos / 1000, DT);
+            pwr = regulator.update(300, estimator.speed(), DT);
+        }
+        assert_eq!(regulator.target(), 300);
+        assert_eq!(regulator.setpoint(), 300);
+        assert!((regulator.speed() - 300).abs() <= 25);
+        assert!((28..=32).contains(&pwr));
+    }
+
+    #[test]
+    fn regulator_limits_acceleration() {
+        let mut regulator = SpeedRegulator::new();
+        for _ in 0..100 {
+            regulator.update(1000, 0, Duration::from_msec(1));
+        }
+        assert_eq!(regulator.setpoint(), 200);
+        regulator.reset();
+        assert!(!regulator.is_active());
+        assert_eq!(regulator.setpoint(), 0);
+    }
+
+    #[test]
+    fn estimator_covers_the_window() {
+        let mut estimator = SpeedEstimator::new(Duration::from_msec(50));
+        assert_eq!(estimator.speed(), 0);
+        // 1 deg every 3ms: too coarse for a single read
+        for i in 0..200 {
+            estimator.update(i / 3, DT);
+        }
+        assert!((estimator.speed() - 333).abs() <= 20);
+        estimator.reset();
+        estimator.update(1000, DT);
+        assert_eq!(estimator.speed(), 0);
+    }
+
+    #[test]
+    fn estimator_without_window() {
+        let mut estimator = SpeedEstimator::new(Duration::zero());
+        estimator.update(0, DT);
+        estimator.update(2, DT);
+        assert_eq!(estimator.speed(), 2000);
+        estimator.update(2, DT);
+        assert_eq!(estimator.speed(), 0);
+    }
+}
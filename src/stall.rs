@@ -0,0 +1,154 @@
+use crate::Duration;
+
+// A motor is stalled when it gets at least min_power (either way) but turns
+// slower than min_speed (deg/s) for time. With cut_power the motor is then
+// left unpowered until MotorData::reset_stall.
+#[derive(Clone, Copy, PartialEq)]
+pub struct StallConfig {
+    pub min_power: i32,
+    pub min_speed: i32,
+    pub time: Duration,
+    pub cut_power: bool,
+}
+
+impl StallConfig {
+    pub const fn new() -> Self {
+        Self {
+            min_power: 20,
+            min_speed: 20,
+            time: Duration::from_msec(500),
+            cut_power: false,
+        }
+    }
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct StallDetector {
+    cfg: StallConfig,
+    slow_for: Duration,
+    stalled: bool,
+    event: bool,
+}
+
+impl StallDetector {
+    pub fn new() -> Self {
+        Self {
+            cfg: StallConfig::new(),
+            slow_for: Duration::zero(),
+            stalled: false,
+            event: false,
+        }
+    }
+
+    pub fn config(&self) -> StallConfig {
+        self.cfg
+    }
+
+    pub fn set_config(&mut self, cfg: StallConfig) {
+        self.cfg = cfg;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.slow_for = Duration::zero();
+        self.stalled = false;
+        self.event = false;
+    }
+
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
+    // Set by the update that found the motor stalled
+    pub fn stall_event(&self) -> bool {
+        self.event
+    }
+
+    // True when the power has to be cut
+    pub fn power_cut(&self) -> bool {
+        self.stalled && self.cfg.cut_power
+    }
+
+    // Takes the power and the measured speed (deg/s, MotorData::speed)
+    pub fn update(&mut self, power: i32, speed: i32, dt: Duration) {
+        self.event = false;
+        if self.power_cut() {
+            // latched until reset
+            return;
+        }
+
+        if power.abs() < self.cfg.min_power || speed.abs() >= self.cfg.min_speed {
+            self.slow_for = Duration::zero();
+            self.stalled = false;
+            return;
+        }
+
+        self.slow_for += dt;
+        if !self.stalled && self.slow_for >= self.cfg.time {
+            self.stalled = true;
+            self.event = true;
+        }
+    }
+}
+
+impl Default for StallDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: Duration = Duration::from_msec(10);
+
+    #[test]
+    fn stalls_when_slow_for_long_enough() {
+        let mut stall = StallDetector::new();
+        for _ in 0..49 {
+            stall.update(50, 5, DT);
+            assert!(!stall.is_stalled());
+        }
+        stall.update(50, 5, DT);
+        assert!(stall.is_stalled() && stall.stall_event());
+        stall.update(50, 5, DT);
+        assert!(stall.is_stalled() && !stall.stall_event());
+        assert!(!stall.power_cut());
+
+        stall.update(50, -100, DT);
+        assert!(!stall.is_stalled());
+    }
+
+    #[test]
+    fn low_power_is_no_stall() {
+        let mut stall = StallDetector::new();
+        for _ in 0..100 {
+            stall.update(10, 0, DT);
+        }
+        assert!(!stall.is_stalled());
+    }
+
+    #[test]
+    fn power_cut_is_latched() {
+        let mut stall = StallDetector::new();
+        stall.set_config(StallConfig {
+            cut_power: true,
+            ..StallConfig::new()
+        });
+        for _ in 0..50 {
+            stall.update(-50, 0, DT);
+        }
+        assert!(stall.power_cut());
+        stall.update(0, 0, DT);
+        assert!(stall.power_cut());
+        stall.reset();
+        assert!(!stall.power_cut());
+    }
+}